@@ -20,7 +20,7 @@ use z4_engine::{
     json,
     request::{message_channel, run_p2p_channel, run_ws_channel, ChannelMessage},
     simple_game_result, Address, Error, HandleResult, Handler, MethodValues, Peer, PeerId, PeerKey,
-    Player, Result, RoomId, Tasks,
+    Player, Result, RoomContext, RoomId, Tasks,
 };
 
 #[allow(dead_code)]
@@ -45,6 +45,11 @@ pub struct ShootHandler {
 impl Handler for ShootHandler {
     type Param = MethodValues;
 
+    /// the demo players join with empty keys
+    fn validate_keys() -> bool {
+        false
+    }
+
     async fn chain_accept(_peers: &[Player]) -> Vec<u8> {
         vec![]
     }
@@ -82,6 +87,7 @@ impl Handler for ShootHandler {
 
     async fn handle(
         &mut self,
+        _ctx: &RoomContext,
         player: PeerId,
         param: MethodValues,
     ) -> Result<HandleResult<Self::Param>> {
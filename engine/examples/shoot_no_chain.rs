@@ -46,38 +46,13 @@ async fn main() {
         ROOM,
         game,
         false,
-        Player {
-            account: H160(id1.0),
-            peer: id1,
-            signer: [0u8; 32],
-        },
+        Player::new(H160(id1.0), id1, [0u8; 32]),
         [0u8; 32],
         [0u8; 32],
     );
-    engine.join_pending(
-        ROOM,
-        Player {
-            account: H160(id2.0),
-            peer: id2,
-            signer: [0u8; 32],
-        },
-    );
-    engine.join_pending(
-        ROOM,
-        Player {
-            account: H160(id3.0),
-            peer: id3,
-            signer: [0u8; 32],
-        },
-    );
-    engine.join_pending(
-        ROOM,
-        Player {
-            account: H160(id4.0),
-            peer: id4,
-            signer: [0u8; 32],
-        },
-    );
+    engine.join_pending(ROOM, Player::new(H160(id2.0), id2, [0u8; 32]));
+    engine.join_pending(ROOM, Player::new(H160(id3.0), id3, [0u8; 32]));
+    engine.join_pending(ROOM, Player::new(H160(id4.0), id4, [0u8; 32]));
 
     let (chain_send, chain_recv) = chain_channel();
     let chain_send1 = chain_send.clone();
@@ -0,0 +1,165 @@
+use serde_json::{json, Value};
+use tdn::{
+    prelude::{Peer, PeerId, SendMessage, SendType},
+    types::rpc::rpc_response,
+};
+use tokio::sync::mpsc::Sender;
+use z4_types::{address_hex, Error, HandleResult, Handler, Result, RoomId};
+
+use crate::{engine::Engine, room::ConnectType, status::RoomStatus};
+
+/// Handle admin rpc message, only the operator with admin key can call them
+pub async fn handle_admin<H: Handler>(
+    engine: &mut Engine<H>,
+    send: &Sender<SendMessage>,
+    uid: u64,
+    mut params: Value,
+    is_ws: bool,
+) -> Result<Option<(HandleResult<H::Param>, RoomId)>> {
+    let id = params["id"].as_u64().unwrap_or(0);
+    let gid = params["gid"].as_u64().unwrap_or(0);
+    let method = params["method"].as_str().unwrap_or("").to_owned();
+    let key = params["key"].as_str().unwrap_or("");
+    if engine.config.admin_key.is_empty() || !key_eq(key, &engine.config.admin_key) {
+        warn!("Admin: invalid key for {}", method);
+        return Err(Error::Auth);
+    }
+
+    let values = params["params"].take();
+    let p = values.as_array().cloned().unwrap_or(vec![]);
+
+    let mut res = None;
+    let result = match method.as_str() {
+        "admin_rooms" => {
+            let mut pendings = vec![];
+            for (rid, proom) in engine.pending.iter() {
+                let players: Vec<String> = proom
+                    .players
+                    .iter()
+                    .map(|p| address_hex(&p.account))
+                    .collect();
+                pendings.push(json!({
+                    "room": rid,
+                    "players": players,
+                    "sequencer": proom.sequencer.as_ref().map(|(s, _)| s.to_hex()),
+                    "age": proom.created.elapsed().as_secs(),
+                }));
+            }
+
+            let mut runnings = vec![];
+            for (rid, hr) in engine.rooms.iter() {
+                let players: Vec<Value> = hr
+                    .room
+                    .players()
+                    .iter()
+                    .map(|p| json!([p.to_hex(), connect_name(hr.room.get(p))]))
+                    .collect();
+                runnings.push(json!({
+                    "room": rid,
+                    "game": address_hex(&hr.game),
+                    "players": players,
+                    "age": hr.created.elapsed().as_secs(),
+                }));
+            }
+
+            json!({
+                "pending": pendings,
+                "running": runnings,
+            })
+        }
+        "admin_room" => {
+            let rid = parse_room(&p, 0)?;
+            if !engine.has_room(&rid) {
+                return Err(Error::NoRoom);
+            }
+            let handler = engine.get_room(&rid).handler.lock().await;
+            handler.debug_state()
+        }
+        "admin_kick" => {
+            let rid = parse_room(&p, 0)?;
+            let peer = PeerId::from_hex(p.get(1).and_then(|v| v.as_str()).unwrap_or(""))?;
+            if !engine.has_room(&rid) {
+                return Err(Error::NoRoom);
+            }
+            info!("Admin: kick {:?} from room {}", peer, rid);
+            if matches!(engine.get_room(&rid).room.get(&peer), ConnectType::P2p) {
+                let disconnect = SendType::Result(0, Peer::peer(peer), false, false, vec![]);
+                let _ = send.send(SendMessage::Group(rid, disconnect)).await;
+            }
+            res = engine
+                .kick_offline(rid, peer)
+                .await?
+                .map(|hres| (hres, rid));
+            json!(true)
+        }
+        "admin_over" => {
+            let rid = parse_room(&p, 0)?;
+            if !engine.has_room(&rid) {
+                return Err(Error::NoRoom);
+            }
+            // the room is over already, proving or submitting its result
+            let status = engine.room_status(&rid).map(|s| s.status);
+            if matches!(status, Some(RoomStatus::Proving | RoomStatus::Submitting)) {
+                return Err(Error::Params);
+            }
+            info!("Admin: force over room {}", rid);
+            let mut hres = HandleResult::default();
+            hres.over();
+            res = Some((hres, rid));
+            json!(true)
+        }
+        "admin_drop" => {
+            let rid = parse_room(&p, 0)?;
+            if !engine.contains_pending(&rid) {
+                return Err(Error::NoRoom);
+            }
+            info!("Admin: drop pending room {}", rid);
+            engine.del_pending(rid);
+            engine.transit(rid, RoomStatus::Expired);
+            json!(true)
+        }
+        "admin_pool" => {
+            let status = engine.pool_status.lock().await;
+            json!({
+                "accepted": status.accepted,
+                "settled": status.settled,
+                "failed": status.failed,
+                "replaced": status.replaced,
+                "inflight": status.inflight,
+                "settling": status.settling,
+            })
+        }
+        _ => return Err(Error::Params),
+    };
+
+    let rpc_msg = rpc_response(id, &method, result, gid);
+    let _ = send.send(SendMessage::Rpc(uid, rpc_msg, is_ws)).await;
+
+    Ok(res)
+}
+
+/// Compare the keys in constant time, not leak the key by the response time
+fn key_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[inline]
+fn parse_room(params: &[Value], index: usize) -> Result<RoomId> {
+    params
+        .get(index)
+        .and_then(|v| v.as_u64())
+        .ok_or(Error::Params)
+}
+
+#[inline]
+fn connect_name(ctype: ConnectType) -> &'static str {
+    match ctype {
+        ConnectType::P2p => "p2p",
+        ConnectType::Rpc(_) => "ws",
+        ConnectType::None => "none",
+    }
+}
@@ -0,0 +1,61 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{
+    select,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        Mutex, RwLock,
+    },
+    task::AbortHandle,
+    time::interval,
+};
+use z4_types::{Bot, Handler, PeerId, RoomContext, RoomId, TaskMessage};
+
+/// Running the bot in the seat, return the sender to deliver messages to it,
+/// and the handle to abort it
+pub fn spawn_bot<H: Handler>(
+    rid: RoomId,
+    peer: PeerId,
+    mut bot: Box<dyn Bot<Param = H::Param>>,
+    handler: Arc<Mutex<H>>,
+    ctx: Arc<RwLock<RoomContext>>,
+    sender: UnboundedSender<TaskMessage<H>>,
+) -> (UnboundedSender<H::Param>, AbortHandle) {
+    let (tx, mut rx) = unbounded_channel();
+    let mut ticker = interval(Duration::from_millis(bot.timer().max(1)));
+
+    let running = tokio::spawn(async move {
+        loop {
+            let (snapshot, params) = select! {
+                msg = rx.recv() => match msg {
+                    Some(param) => {
+                        let snapshot = ctx.read().await.clone();
+                        let params = bot.receive(&snapshot, param).await;
+                        (snapshot, params)
+                    }
+                    None => break,
+                },
+                _ = ticker.tick() => {
+                    let snapshot = ctx.read().await.clone();
+                    let params = bot.act(&snapshot).await;
+                    (snapshot, params)
+                }
+            };
+
+            for param in params {
+                let mut handler_lock = handler.lock().await;
+                let res = handler_lock.handle(&snapshot, peer, param).await;
+                drop(handler_lock);
+
+                match res {
+                    Ok(res) => {
+                        let _ = sender.send(TaskMessage::Result(rid, res));
+                    }
+                    Err(err) => debug!("Bot: {:?} in room {}: {:?}", peer, rid, err),
+                }
+            }
+        }
+    });
+
+    (tx, running.abort_handle())
+}
@@ -0,0 +1,148 @@
+use std::sync::Arc;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use z4_types::{Handler, Result};
+
+use crate::{
+    config::Config,
+    engine::{Engine, TransportChannel},
+    handle::EngineHandle,
+    observer::EngineObserver,
+    policy::AcceptPolicy,
+    registry::GameRegistry,
+    storage::Storage,
+    ChainMessage,
+};
+
+/// Chain backend channel, send and receive chain messages
+type ChainChannel = (UnboundedSender<ChainMessage>, UnboundedReceiver<ChainMessage>);
+
+/// Builder to embed the engine in other application
+pub struct EngineBuilder<H: Handler> {
+    config: Config,
+    chain: Option<ChainChannel>,
+    transport: Option<TransportChannel>,
+    storage: Option<Arc<dyn Storage>>,
+    policy: Option<Arc<dyn AcceptPolicy>>,
+    registry: GameRegistry<H>,
+    observers: Vec<Arc<dyn EngineObserver>>,
+}
+
+impl<H: Handler> EngineBuilder<H> {
+    /// New builder with config
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            chain: None,
+            transport: None,
+            storage: None,
+            policy: None,
+            registry: GameRegistry::default(),
+            observers: vec![],
+        }
+    }
+
+    /// New builder with config from env
+    pub fn from_env() -> Result<Self> {
+        Ok(Self::new(Config::from_env()?))
+    }
+
+    /// Use the chain channel as chain backend, instead of scan from chain
+    pub fn chain_channel(
+        mut self,
+        sender: UnboundedSender<ChainMessage>,
+        receiver: UnboundedReceiver<ChainMessage>,
+    ) -> Self {
+        self.chain = Some((sender, receiver));
+        self
+    }
+
+    /// Use the network transport, instead of start TDN with the ports
+    pub fn transport(mut self, transport: TransportChannel) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Set the http rpc port
+    pub fn http_port(mut self, port: u16) -> Self {
+        self.config.http_port = port;
+        self
+    }
+
+    /// Set the websocket port, none is disabled
+    pub fn ws_port(mut self, port: Option<u16>) -> Self {
+        self.config.ws_port = port;
+        self
+    }
+
+    /// Set the p2p port
+    pub fn p2p_port(mut self, port: u16) -> Self {
+        self.config.p2p_port = port;
+        self
+    }
+
+    /// Set the storage of engine states
+    pub fn storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Set the policy to accept rooms
+    pub fn accept_policy(mut self, policy: Arc<dyn AcceptPolicy>) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Set the games registry
+    pub fn registry(mut self, registry: GameRegistry<H>) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Add the observer of engine lifecycle
+    pub fn observer(mut self, observer: Arc<dyn EngineObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Build the engine
+    pub fn build(self) -> Engine<H> {
+        self.build_with_chain().0
+    }
+
+    fn build_with_chain(self) -> (Engine<H>, Option<ChainChannel>) {
+        let mut engine = Engine::init(self.config);
+        if let Some(transport) = self.transport {
+            engine.set_transport(transport);
+        }
+        if let Some(storage) = self.storage {
+            engine.set_storage(storage);
+        }
+        if let Some(policy) = self.policy {
+            engine.set_accept_policy(policy);
+        }
+        engine.set_registry(self.registry);
+        for observer in self.observers {
+            engine.add_observer(observer);
+        }
+        (engine, self.chain)
+    }
+
+    /// Start the engine in background, return the handle to control it
+    pub fn start(self) -> EngineHandle<H::Param> {
+        let (engine, chain) = self.build_with_chain();
+        let handle = engine.handle();
+        tokio::spawn(async move {
+            // the injected chain channel is the chain backend, not scan the configured chains
+            let res = match chain {
+                Some((chain_send, chain_recv)) => {
+                    engine.run_with_chains(vec![], chain_send, chain_recv).await
+                }
+                None => engine.run().await,
+            };
+            if let Err(err) = res {
+                error!("Engine: {:?}", err);
+            }
+        });
+        handle
+    }
+}
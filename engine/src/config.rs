@@ -1,12 +1,35 @@
-use ethers::prelude::{Address, Http, LocalWallet, Provider, SignerMiddleware, U256};
+use ethers::prelude::{Address, Http, LocalWallet, Provider, Signer, SignerMiddleware, U256};
 use std::{path::PathBuf, sync::Arc};
 use tdn::prelude::{Config as TdnConfig, PeerKey};
 use z4_types::{
-    env_value, env_values, hex_address, Network, NetworkConfig, Result, Z4_ROOM_MARKET_GROUP,
+    env_value, env_values, hex_address, Error, Network, NetworkConfig, Result,
+    LOCAL_ROOM_NAMESPACE, Z4_ROOM_MARKET_GROUP,
 };
 
 use crate::contracts::{RoomMarket, Token};
 
+/// Chain params of a (network, market) pair
+pub struct ChainParams {
+    /// the namespace of room ids, 0 for main market and chain id for extra markets
+    pub namespace: usize,
+    /// the chain network
+    pub network: Network,
+    /// http providers for scan
+    pub providers: Vec<Arc<Provider<Http>>>,
+    /// websocket urls to subscribe logs
+    pub ws_urls: Vec<String>,
+    /// signer to send transactions
+    pub signer: Arc<SignerMiddleware<Arc<Provider<Http>>, LocalWallet>>,
+    /// room market address
+    pub market: Address,
+    /// scan start block, override the saved checkpoint
+    pub start_block: Option<u64>,
+    /// the block which market deployed, none is searched from the providers
+    pub deploy_block: Option<u64>,
+    /// blocks of confirmation when scan
+    pub confirmations: u64,
+}
+
 /// config of engine
 #[derive(Default)]
 pub struct Config {
@@ -26,16 +49,72 @@ pub struct Config {
     pub p2p_port: u16,
     /// the chain network name
     pub chain_network: String,
-    /// the chain rpcs
+    /// the chain rpcs, websocket urls will be used to subscribe logs
     pub chain_rpcs: Vec<String>,
-    /// scan start block
+    /// extra (network, room market, rpcs, confirmations) pairs, scan and settle at the same time,
+    /// the chain id is the namespace of room ids, so one extra market each chain,
+    /// none confirmations is the network default
+    pub chain_markets: Vec<(String, String, Vec<String>, Option<u64>)>,
+    /// scan start block of main market, override the saved checkpoint
     pub chain_start_block: Option<u64>,
+    /// the block which main market deployed (DEPLOY_BLOCK), the sequencers are backfilled
+    /// from it, none is searched from the state of providers, which requires archive nodes
+    pub chain_deploy_block: Option<u64>,
+    /// blocks of confirmation when scan, none is the network default
+    pub chain_confirmations: Option<u64>,
+    /// providers which report the same log before it is handled, 1 is trust anyone
+    pub chain_quorum: usize,
     /// auto stake to sequencer
     pub auto_stake: bool,
     /// http url for this service
     pub url_http: String,
     /// http url for this service
     pub url_websocket: String,
+    /// seconds between two heartbeat checks
+    pub heartbeat_interval: u64,
+    /// seconds without any message before a websocket player is offline (IDLE_TIMEOUT),
+    /// default is 30, 0 is disabled. The closed websocket is not reported by the rpc layer,
+    /// so it is only detected by this timeout, and clients keep alive with `z4_ping`
+    pub idle_timeout: u64,
+    /// max bytes of a message payload, 0 is unlimited
+    pub max_payload: usize,
+    /// messages per second of a peer in a room, 0 is unlimited
+    pub peer_rate: u32,
+    /// max burst messages of a peer in a room
+    pub peer_burst: u32,
+    /// messages per second of a room, 0 is unlimited
+    pub room_rate: u32,
+    /// max burst messages of a room
+    pub room_burst: u32,
+    /// times of limit breaches before the peer is disconnected, 0 is never
+    pub max_violations: u32,
+    /// the key for admin rpc methods, empty is disabled
+    pub admin_key: String,
+    /// the metrics http port
+    pub metrics_port: Option<u16>,
+    /// seconds for running rooms to finish when shutdown
+    pub shutdown_timeout: u64,
+    /// the directory of engine storage, empty is in memory
+    pub storage_path: String,
+    /// seconds before a pending room which not started expired (PENDING_TTL),
+    /// default is 3600, 0 is disabled
+    pub pending_ttl: u64,
+    /// blocks of the chain scanned before a pending room which not started expired
+    /// (PENDING_BLOCKS), default is 0 which is disabled, it depends on the block time of chain
+    pub pending_blocks: u64,
+    /// seconds without any player activity before a running room is idle (ROOM_IDLE),
+    /// default is 1800, 0 is disabled
+    pub room_idle: u64,
+    /// seconds before fill the empty seats of not started room with bots, 0 is disabled
+    pub bot_fill: u64,
+    /// seconds to delay the broadcast messages for viewers, 0 is no delay
+    pub viewer_delay: u64,
+    /// max bytes of a chat message
+    pub chat_size: usize,
+    /// max chat messages kept in the room history
+    pub chat_history: usize,
+    /// only players can send and receive the chat messages
+    pub chat_players_only: bool,
 }
 
 impl Config {
@@ -47,15 +126,51 @@ impl Config {
         let games: Vec<String> = env_values("GAMES", None)?;
         let secret_key = env_value("SECRET_KEY", None)?;
         let start_block = env_value("START_BLOCK", None).ok();
+        let deploy_block = env_value("DEPLOY_BLOCK", None).ok();
+        let confirmations = env_value("CONFIRMATIONS", None).ok();
+        let quorum = env_value("SCAN_QUORUM", Some(1))?;
 
         let chain_rpcs = env_values("RPC_ENDPOINTS", Some(vec![]))?;
         let room_market = env_value("ROOM_MARKET", Some(games[0].clone()))?;
+        let markets: Vec<String> = env_values("MARKETS", Some(vec![]))?;
+        let mut chain_markets = vec![];
+        for market in markets {
+            // network:market, rpcs from RPC_ENDPOINTS_{NETWORK},
+            // and confirmations from CONFIRMATIONS_{NETWORK}
+            if let Some((network, address)) = market.split_once(':') {
+                let key = format!("RPC_ENDPOINTS_{}", network.to_uppercase());
+                let rpcs = env_values(&key, Some(vec![]))?;
+                let key = format!("CONFIRMATIONS_{}", network.to_uppercase());
+                let confirmations = env_value(&key, None).ok();
+                chain_markets.push((network.to_owned(), address.to_owned(), rpcs, confirmations));
+            }
+        }
         let url_http = env_value("URL_HTTP", Some("".to_owned()))?;
         let url_websocket = env_value("URL_WEBSOCKET", Some("".to_owned()))?;
         let http_port = env_value("HTTP_PORT", Some(8080))?;
         let ws_port = env_value("WS_PORT", Some(8000))?;
         let p2p_port = env_value("P2P_PORT", Some(7364))?;
         let auto_stake = env_value("AUTO_STAKE", Some(false))?;
+        let heartbeat_interval = env_value("HEARTBEAT_INTERVAL", Some(10))?;
+        let idle_timeout = env_value("IDLE_TIMEOUT", Some(30))?;
+        let max_payload = env_value("MAX_PAYLOAD", Some(65536))?;
+        let peer_rate = env_value("PEER_RATE", Some(20))?;
+        let peer_burst = env_value("PEER_BURST", Some(40))?;
+        let room_rate = env_value("ROOM_RATE", Some(200))?;
+        let room_burst = env_value("ROOM_BURST", Some(400))?;
+        let max_violations = env_value("MAX_VIOLATIONS", Some(10))?;
+        let admin_key = env_value("ADMIN_KEY", Some("".to_owned()))?;
+        let metrics_port = env_value("METRICS_PORT", None).ok();
+        let shutdown_timeout = env_value("SHUTDOWN_TIMEOUT", Some(60))?;
+        let storage_path = env_value("STORAGE_PATH", Some("./.z4".to_owned()))?;
+        let pending_ttl = env_value("PENDING_TTL", Some(3600))?;
+        let pending_blocks = env_value("PENDING_BLOCKS", Some(0))?;
+        let room_idle = env_value("ROOM_IDLE", Some(1800))?;
+        let bot_fill = env_value("BOT_FILL", Some(0))?;
+        let viewer_delay = env_value("VIEWER_DELAY", Some(0))?;
+        let chat_size = env_value("CHAT_SIZE", Some(256))?;
+        let chat_history = env_value("CHAT_HISTORY", Some(50))?;
+        let chat_players_only = env_value("CHAT_PLAYERS_ONLY", Some(false))?;
 
         let mut config = Config::default();
         config.http_port = http_port;
@@ -65,11 +180,35 @@ impl Config {
         config.chain_network = network;
         config.chain_rpcs = chain_rpcs;
         config.chain_start_block = start_block;
+        config.chain_confirmations = confirmations;
+        config.chain_deploy_block = deploy_block;
+        config.chain_quorum = quorum;
         config.games = games;
         config.room_market = room_market;
+        config.chain_markets = chain_markets;
         config.auto_stake = auto_stake;
         config.url_http = url_http;
         config.url_websocket = url_websocket;
+        config.heartbeat_interval = heartbeat_interval;
+        config.idle_timeout = idle_timeout;
+        config.max_payload = max_payload;
+        config.peer_rate = peer_rate;
+        config.peer_burst = peer_burst;
+        config.room_rate = room_rate;
+        config.room_burst = room_burst;
+        config.max_violations = max_violations;
+        config.admin_key = admin_key;
+        config.metrics_port = metrics_port;
+        config.shutdown_timeout = shutdown_timeout;
+        config.storage_path = storage_path;
+        config.pending_ttl = pending_ttl;
+        config.pending_blocks = pending_blocks;
+        config.room_idle = room_idle;
+        config.bot_fill = bot_fill;
+        config.viewer_delay = viewer_delay;
+        config.chat_size = chat_size;
+        config.chat_history = chat_history;
+        config.chat_players_only = chat_players_only;
 
         Ok(config)
     }
@@ -97,25 +236,80 @@ impl Config {
         (config, key)
     }
 
-    /// Convert config to chain params
-    pub async fn to_chain(
-        &self,
-    ) -> Option<(
-        Vec<Arc<Provider<Http>>>,
-        Arc<SignerMiddleware<Arc<Provider<Http>>, LocalWallet>>,
-        Address,
-        Option<u64>,
-    )> {
+    /// Convert config to chain params of all (network, market) pairs,
+    /// the main market is namespace 0 and the chain id is the namespace of extra markets
+    pub async fn to_chains(&self) -> Result<Vec<ChainParams>> {
+        let mut chains = vec![];
         if self.chain_network.is_empty() {
-            return None;
+            return Ok(chains);
         }
 
+        let room_market = if self.room_market.is_empty() {
+            &self.games[0]
+        } else {
+            &self.room_market
+        };
         let network = Network::from_str(&self.chain_network);
+        let confirmations = self
+            .chain_confirmations
+            .unwrap_or_else(|| network.confirmations());
+        let mut params = self
+            .chain_params(
+                network,
+                room_market,
+                &self.chain_rpcs,
+                self.chain_start_block,
+                confirmations,
+            )
+            .await?;
+        params.deploy_block = self.chain_deploy_block;
+        chains.push(params);
+
+        for (network, market, rpcs, confirmations) in self.chain_markets.iter() {
+            let network = Network::from_str(network);
+            let confirmations = confirmations.unwrap_or_else(|| network.confirmations());
+            let mut params = self
+                .chain_params(network, market, rpcs, None, confirmations)
+                .await?;
+            // the extra markets use the chain id as namespace
+            let chain_id = params.signer.signer().chain_id();
+            if chain_id == 0 || chain_id >= LOCAL_ROOM_NAMESPACE {
+                return Err(Error::Anyhow("MARKETS env invalid chain id".to_owned()));
+            }
+            params.namespace = chain_id as usize;
+            if chains.iter().any(|c| c.namespace == params.namespace) {
+                return Err(Error::Anyhow("MARKETS env has same chain".to_owned()));
+            }
+            chains.push(params);
+        }
+
+        Ok(chains)
+    }
+
+    /// Build providers and signer of the pair, auto stake if needed,
+    /// the websocket endpoints only subscribe logs, so the http endpoint is required with them
+    async fn chain_params(
+        &self,
+        network: Network,
+        room_market: &str,
+        chain_rpcs: &[String],
+        start_block: Option<u64>,
+        confirmations: u64,
+    ) -> Result<ChainParams> {
         let nc = NetworkConfig::from(network);
-        let rpcs = if self.chain_rpcs.is_empty() {
+        let (ws_rpcs, http_rpcs): (Vec<String>, Vec<String>) = chain_rpcs
+            .iter()
+            .cloned()
+            .partition(|rpc| rpc.starts_with("ws://") || rpc.starts_with("wss://"));
+        if http_rpcs.is_empty() && !ws_rpcs.is_empty() {
+            return Err(Error::Anyhow(
+                "RPC_ENDPOINTS env missing http endpoint".to_owned(),
+            ));
+        }
+        let rpcs = if http_rpcs.is_empty() {
             &nc.rpc_urls
         } else {
-            &self.chain_rpcs
+            &http_rpcs
         };
         let providers: Vec<_> = rpcs
             .iter()
@@ -133,11 +327,6 @@ impl Config {
                 .unwrap(),
         );
 
-        let room_market = if self.room_market.is_empty() {
-            &self.games[0]
-        } else {
-            &self.room_market
-        };
         let market_address = hex_address(room_market).expect("Invalid room market address");
         if self.auto_stake && (!self.url_http.is_empty() || !self.url_websocket.is_empty()) {
             // check & register sequencer
@@ -174,11 +363,16 @@ impl Config {
             }
         }
 
-        Some((
+        Ok(ChainParams {
+            namespace: 0,
+            network,
             providers,
-            signer_provider,
-            market_address,
-            self.chain_start_block,
-        ))
+            ws_urls: ws_rpcs,
+            signer: signer_provider,
+            market: market_address,
+            start_block,
+            deploy_block: None,
+            confirmations,
+        })
     }
 }
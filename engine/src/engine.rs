@@ -1,7 +1,8 @@
-use ethers::prelude::Address;
+use ethers::{prelude::Address, utils::keccak256};
 use serde_json::{json, Value};
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{atomic::Ordering, Arc};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tdn::{
     prelude::{
         start_with_config_and_key, NetworkType, PeerId, ReceiveMessage, SendMessage, SendType,
@@ -10,21 +11,35 @@ use tdn::{
 };
 use tokio::{
     select,
-    sync::mpsc::{unbounded_channel, Sender, UnboundedReceiver, UnboundedSender},
-    sync::Mutex,
+    sync::mpsc::{unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+    sync::{broadcast, oneshot, Mutex, RwLock},
+    task::AbortHandle,
+    time::{interval, sleep_until, timeout},
 };
 use z4_types::{
-    handle_tasks, GameId, HandleResult, Handler, MethodValues, Param, Player, Result, RoomId,
+    handle_tasks_with_context, is_local_room, split_room_id, Bots, Error, GameId, HandleResult,
+    Handler, MethodValues, Param, Player, Result, RoomContext, RoomId, SystemMessage,
     TaskMessage,
 };
 
 use crate::{
-    config::Config,
+    admin::handle_admin,
+    bot::spawn_bot,
+    config::{ChainParams, Config},
+    handle::{EngineCommand, EngineEvent, EngineHandle, RoomInfo},
+    limit::Limiter,
+    metrics::{listen as metrics_listen, Metrics, Transport},
+    observer::{observe, EngineObserver},
     p2p::handle_p2p,
-    pool::{listen as pool_listen, pool_channel},
+    policy::{AcceptAll, AcceptPolicy},
+    pool::{listen as pool_listen, pool_channel, PoolConfig, PoolStatus},
+    registry::{GameRegistry, Games},
     room::{ConnectType, Room},
     rpc::handle_rpc,
-    scan::{chain_channel, listen as scan_listen},
+    scan::{chain_channel, listen as scan_listen, ScanConfig},
+    sequencer::Sequencers,
+    status::{now as status_now, RoomLifecycle, RoomStatus, STATUS_KEEP},
+    storage::{FileStorage, MemoryStorage, Storage},
     ChainMessage, PoolMessage,
 };
 
@@ -36,6 +51,22 @@ pub struct HandlerRoom<H: Handler> {
     pub game: GameId,
     /// Room info
     pub room: Room,
+    /// The time when room started
+    pub created: Instant,
+    /// The time of latest player activity
+    pub active: Instant,
+    /// The running tasks & bots of room
+    tasks: Vec<AbortHandle>,
+    /// The bots in seats, peer => messages sender
+    bots: HashMap<PeerId, UnboundedSender<H::Param>>,
+    /// The empty seats had been filled with bots
+    filled: bool,
+    /// The delay queue of viewers messages
+    delay: Option<UnboundedSender<(Instant, Vec<SendMessage>)>>,
+    /// The chat history of room, sender, text, unix timestamp
+    chat: VecDeque<(PeerId, String, u64)>,
+    /// The context of room
+    pub context: Arc<RwLock<RoomContext>>,
 }
 
 /// Pending room
@@ -52,22 +83,65 @@ pub struct PendingRoom {
     pub players: Vec<Player>,
     /// Sequencer params: peer, websocket
     pub sequencer: Option<(PeerId, String)>,
+    /// The time when room created
+    pub created: Instant,
+    /// The scanned block height of chain when room created, none is local or not scanned
+    pub height: Option<u64>,
 }
 
 /// Engine
 pub struct Engine<H: Handler> {
     /// Config of engine and network
-    config: Config,
+    pub(crate) config: Config,
     /// Rooms which is running
-    rooms: HashMap<RoomId, HandlerRoom<H>>,
+    pub(crate) rooms: HashMap<RoomId, HandlerRoom<H>>,
     /// Rooms which is waiting create, room => (game, players, sequencer)
     pub pending: HashMap<RoomId, PendingRoom>,
     /// Supported games and game's pending rooms
     pub games: HashMap<GameId, Vec<RoomId>>,
     /// Connected peers
     onlines: Arc<Mutex<HashMap<PeerId, Vec<RoomId>>>>,
+    /// Websocket connections last active time, uid => (peer, time)
+    heartbeats: HashMap<u64, (PeerId, Instant)>,
+    /// Messages size & rate limiter
+    limiter: Limiter,
+    /// Pool transactions status
+    pub(crate) pool_status: Arc<Mutex<PoolStatus>>,
+    /// Metrics registry
+    pub(crate) metrics: Arc<Metrics>,
+    /// Registered games handler factories
+    registry: GameRegistry<H>,
+    /// Control commands sender
+    command_sender: UnboundedSender<EngineCommand<H::Param>>,
+    /// Control commands receiver, take it when running
+    command_receiver: Option<UnboundedReceiver<EngineCommand<H::Param>>>,
+    /// Engine events sender
+    events: broadcast::Sender<EngineEvent>,
+    /// Storage of engine states
+    storage: Arc<dyn Storage>,
+    /// Policy to accept rooms
+    policy: Arc<dyn AcceptPolicy>,
+    /// Observers of engine lifecycle
+    observers: Vec<Arc<dyn EngineObserver>>,
+    /// Rooms lifecycle status
+    statuses: HashMap<RoomId, RoomLifecycle>,
+    /// Tasks & bots results sender
+    task_sender: UnboundedSender<TaskMessage<H>>,
+    /// Tasks & bots results receiver, take it when running
+    task_receiver: Option<UnboundedReceiver<TaskMessage<H>>>,
+    /// Presence changes waiting broadcast, room, peer, is online
+    presence: Vec<(RoomId, PeerId, bool)>,
+    /// Active sequencers synced from chain
+    sequencers: Sequencers,
+    /// Injected network transport, none will start TDN with config
+    transport: Option<TransportChannel>,
+    /// The block heights of chains scanned by quorum, namespace => height
+    heights: HashMap<usize, u64>,
 }
 
+/// Network transport channel, the peer id of this node, send and receive messages
+pub type TransportChannel = (PeerId, Sender<SendMessage>, Receiver<ReceiveMessage>);
+
 impl<H: Handler> Engine<H> {
     /// Init a engine with config
     pub fn init(config: Config) -> Self {
@@ -77,13 +151,148 @@ impl<H: Handler> Engine<H> {
                 games.insert(addr, vec![]);
             }
         }
+        let limiter = Limiter::new(&config);
+        let (command_sender, command_receiver) = unbounded_channel();
+        let (task_sender, task_receiver) = unbounded_channel();
+        let (events, _) = broadcast::channel(1024);
+        let storage: Arc<dyn Storage> = if config.storage_path.is_empty() {
+            Arc::new(MemoryStorage::default())
+        } else {
+            match FileStorage::new(&config.storage_path) {
+                Ok(storage) => Arc::new(storage),
+                Err(err) => {
+                    error!("Storage: {:?}, use memory storage", err);
+                    Arc::new(MemoryStorage::default())
+                }
+            }
+        };
         Self {
             config,
             games,
+            limiter,
             rooms: HashMap::new(),
             pending: HashMap::new(),
             onlines: Arc::new(Mutex::new(HashMap::new())),
+            heartbeats: HashMap::new(),
+            pool_status: Arc::new(Mutex::new(PoolStatus::default())),
+            metrics: Arc::new(Metrics::default()),
+            command_sender,
+            command_receiver: Some(command_receiver),
+            registry: GameRegistry::default(),
+            events,
+            storage,
+            policy: Arc::new(AcceptAll),
+            observers: vec![],
+            statuses: HashMap::new(),
+            task_sender,
+            task_receiver: Some(task_receiver),
+            presence: vec![],
+            sequencers: Sequencers::default(),
+            transport: None,
+            heights: HashMap::new(),
+        }
+    }
+
+    /// Get the games registry, to register handler factories
+    pub fn registry(&mut self) -> &mut GameRegistry<H> {
+        &mut self.registry
+    }
+
+    /// Replace the games registry, and support the registered games
+    pub fn set_registry(&mut self, registry: GameRegistry<H>) {
+        for game in registry.games() {
+            self.games.entry(game).or_default();
         }
+        self.registry = registry;
+    }
+
+    /// Use the injected network transport, instead of start TDN with config
+    pub fn set_transport(&mut self, transport: TransportChannel) {
+        self.transport = Some(transport);
+    }
+
+    /// Replace the storage of engine states
+    pub fn set_storage(&mut self, storage: Arc<dyn Storage>) {
+        self.storage = storage;
+    }
+
+    /// Get the storage of engine states
+    pub fn storage(&self) -> Arc<dyn Storage> {
+        self.storage.clone()
+    }
+
+    /// Replace the policy to accept rooms
+    pub fn set_accept_policy(&mut self, policy: Arc<dyn AcceptPolicy>) {
+        self.policy = policy;
+    }
+
+    /// Add the observer of engine lifecycle
+    pub fn add_observer(&mut self, observer: Arc<dyn EngineObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Get the room info
+    pub fn room_info(&self, id: &RoomId) -> Option<RoomInfo> {
+        self.rooms.get(id).map(|hr| RoomInfo {
+            id: *id,
+            game: hr.game,
+            players: hr.room.players().to_vec(),
+            age: hr.created.elapsed().as_secs(),
+        })
+    }
+
+    /// Get the room lifecycle status
+    pub fn room_status(&self, id: &RoomId) -> Option<&RoomLifecycle> {
+        self.statuses.get(id)
+    }
+
+    /// Transit the room to next status, invalid transition or unknown room will be ignored
+    pub fn transit(&mut self, id: RoomId, status: RoomStatus) -> bool {
+        let lifecycle = if let Some(lifecycle) = self.statuses.get_mut(&id) {
+            lifecycle
+        } else {
+            return false;
+        };
+        if lifecycle.status == status {
+            return true;
+        }
+        let ok = lifecycle.transit(status);
+        if !ok {
+            warn!(
+                "Engine: invalid room {} status {} => {}",
+                id,
+                lifecycle.status.as_str(),
+                status.as_str()
+            );
+        }
+        ok
+    }
+
+    /// Remove the finished room status which kept too long
+    fn prune_statuses(&mut self) {
+        let now = status_now();
+        self.statuses
+            .retain(|_, s| !(s.status.is_final() && s.updated() + STATUS_KEEP < now));
+    }
+
+    /// Get the active sequencers synced from chain
+    pub fn sequencers(&self) -> &Sequencers {
+        &self.sequencers
+    }
+
+    /// Get the handle to control the engine when running
+    pub fn handle(&self) -> EngineHandle<H::Param> {
+        EngineHandle::new(self.command_sender.clone(), self.events.clone())
+    }
+
+    /// Update the rooms metrics
+    fn update_metrics(&self) {
+        self.metrics
+            .rooms_pending
+            .store(self.pending.len() as u64, Ordering::Relaxed);
+        self.metrics
+            .rooms_running
+            .store(self.rooms.len() as u64, Ordering::Relaxed);
     }
 
     /// Create a pending room when scan from chain
@@ -96,6 +305,7 @@ impl<H: Handler> Engine<H> {
         salt: [u8; 32],
         block: [u8; 32],
     ) {
+        let height = self.heights.get(&split_room_id(id).0).copied();
         if let Some(games) = self.games.get_mut(&game) {
             if !self.pending.contains_key(&id) {
                 self.pending.insert(
@@ -107,17 +317,34 @@ impl<H: Handler> Engine<H> {
                         block,
                         players: vec![player],
                         sequencer: None,
+                        created: Instant::now(),
+                        height,
                     },
                 );
                 games.push(id);
+                self.statuses.insert(id, RoomLifecycle::new());
+                let _ = self.events.send(EngineEvent::PendingCreated(id, game));
             }
         }
+        self.update_metrics();
     }
 
     /// Join new player to the room
     pub fn join_pending(&mut self, id: RoomId, player: Player) {
         if let Some(proom) = self.pending.get_mut(&id) {
+            // the logs maybe scanned again after restart or reorg
+            if proom.players.iter().any(|p| p.peer == player.peer) {
+                return;
+            }
             proom.players.push(player);
+            let _ = self.events.send(EngineEvent::PendingJoined(id, player));
+        }
+    }
+
+    /// Remove the player from pending room
+    pub fn leave_pending(&mut self, id: RoomId, peer: &PeerId) {
+        if let Some(proom) = self.pending.get_mut(&id) {
+            proom.players.retain(|p| &p.peer != peer);
         }
     }
 
@@ -128,6 +355,7 @@ impl<H: Handler> Engine<H> {
                 .get_mut(&proom.game)
                 .map(|v| vec_remove_item(v, &id));
         }
+        self.update_metrics();
     }
 
     /// Check if contains pending room
@@ -145,7 +373,11 @@ impl<H: Handler> Engine<H> {
         task_sender: UnboundedSender<TaskMessage<H>>,
     ) {
         if let Some(proom) = self.pending.get_mut(&id) {
+            let sequencer_peer = sequencer.0;
             proom.sequencer = Some(sequencer);
+            if let Some(lifecycle) = self.statuses.get_mut(&id) {
+                lifecycle.transit(RoomStatus::Running);
+            }
 
             if is_self {
                 let seed: [u8; 32] = proom
@@ -156,36 +388,427 @@ impl<H: Handler> Engine<H> {
                     .collect::<Vec<u8>>()
                     .try_into()
                     .unwrap_or([0u8; 32]);
-                if let Some((raw_handler, tasks)) =
-                    H::chain_create(&proom.players, params, id, seed).await
+                if let Some((raw_handler, tasks)) = self
+                    .registry
+                    .create(&proom.game, &proom.players, params, id, seed)
+                    .await
                 {
+                    let lobby = raw_handler.lobby();
                     let handler = Arc::new(Mutex::new(raw_handler));
                     let ids: Vec<PeerId> = proom.players.iter().map(|p| p.peer).collect();
+                    let context = Arc::new(RwLock::new(RoomContext::new(
+                        id,
+                        proom.game,
+                        proom.players.clone(),
+                        proom.viewable,
+                        seed,
+                        sequencer_peer,
+                    )));
 
                     // running tasks
-                    if !tasks.is_empty() {
-                        handle_tasks(id, tasks, handler.clone(), task_sender);
+                    let tasks = if !tasks.is_empty() {
+                        handle_tasks_with_context(
+                            id,
+                            context.clone(),
+                            tasks,
+                            handler.clone(),
+                            task_sender,
+                        )
+                    } else {
+                        vec![]
+                    };
+
+                    let mut room = Room::new(id, proom.viewable, &ids);
+                    if !lobby {
+                        room.start();
                     }
 
                     let room = HandlerRoom {
                         handler: handler,
                         game: proom.game,
-                        room: Room::new(id, proom.viewable, &ids),
+                        room,
+                        created: Instant::now(),
+                        active: Instant::now(),
+                        tasks,
+                        context,
+                        bots: HashMap::new(),
+                        filled: false,
+                        delay: None,
+                        chat: VecDeque::new(),
                     };
 
                     self.rooms.insert(id, room);
+                    let _ = self.events.send(EngineEvent::RoomStarted(id));
+                    self.attach_bots(id).await;
                 }
             }
         }
+        self.update_metrics();
     }
 
     /// Over a room
     pub async fn over_room(&mut self, id: RoomId) {
-        if let Some(_room) = self.rooms.remove(&id) {
+        if let Some(room) = self.rooms.remove(&id) {
             // TODO clear onlines
+            for task in room.tasks {
+                task.abort();
+            }
+            let _ = self.events.send(EngineEvent::RoomOver(id));
+        }
+        self.limiter.remove_room(id);
+        self.update_metrics();
+    }
+
+    /// New player join the running room when it is not started
+    pub async fn join_room(
+        &mut self,
+        id: RoomId,
+        player: Player,
+        params: Vec<u8>,
+    ) -> Result<HandleResult<H::Param>> {
+        let hr = self.rooms.get_mut(&id).ok_or(Error::NoRoom)?;
+        if hr.room.is_started() {
+            return Err(Error::RoomLocked);
+        }
+        if hr.room.is_player(&player.peer) {
+            return Ok(HandleResult::default());
+        }
+
+        let ctx = hr.context.read().await.clone();
+        // same as the players when room accepted, the key is required unless game opts out
+        if !self.registry.validate(&ctx.game, &[player]) {
+            return Err(Error::Auth);
+        }
+        let mut handler = hr.handler.lock().await;
+        let res = handler.join(&ctx, player, params).await?;
+        drop(handler);
+
+        hr.room.join(player.peer);
+        hr.context.write().await.players.push(player);
+        hr.active = Instant::now();
+        let _ = self.events.send(EngineEvent::PlayerJoined(id, player));
+        Ok(res)
+    }
+
+    /// Player leave the room, the seat will be removed when room not started
+    pub async fn leave_room(&mut self, id: RoomId, peer: PeerId) -> Result<HandleResult<H::Param>> {
+        let hr = self.rooms.get_mut(&id).ok_or(Error::NoRoom)?;
+        if !hr.room.is_player(&peer) {
+            return Err(Error::NoPlayer);
+        }
+
+        let ctx = hr.context.read().await.clone();
+        let mut handler = hr.handler.lock().await;
+        let res = handler.leave(&ctx, peer).await?;
+        drop(handler);
+
+        hr.bots.remove(&peer);
+        if hr.room.leave(&peer) {
+            hr.context.write().await.players.retain(|p| p.peer != peer);
+        } else {
+            hr.room.offline(peer);
+        }
+        self.remove_online(peer, id).await;
+        let _ = self.events.send(EngineEvent::PlayerLeft(id, peer));
+        Ok(res)
+    }
+
+    /// Player forfeit the game, handler decides to over it or replace the seat
+    pub async fn forfeit_room(
+        &mut self,
+        id: RoomId,
+        peer: PeerId,
+    ) -> Result<HandleResult<H::Param>> {
+        let hr = self.rooms.get(&id).ok_or(Error::NoRoom)?;
+        if !hr.room.is_player(&peer) {
+            return Err(Error::NoPlayer);
+        }
+
+        let ctx = hr.context.read().await.clone();
+        let mut handler = hr.handler.lock().await;
+        let res = handler.forfeit(&ctx, peer).await?;
+        drop(handler);
+
+        let _ = self.events.send(EngineEvent::PlayerForfeit(id, peer));
+        Ok(res)
+    }
+
+    /// Replace the seat of player to new player, connections follow the new peer
+    async fn replace_player(&mut self, id: RoomId, old: PeerId, player: Player) {
+        if let Some(hr) = self.rooms.get_mut(&id) {
+            if !hr.room.replace(&old, player.peer) {
+                return;
+            }
+            hr.bots.remove(&old);
+            for p in hr.context.write().await.players.iter_mut() {
+                if p.peer == old {
+                    *p = player;
+                }
+            }
+            self.remove_online(old, id).await;
+            let _ = self.events.send(EngineEvent::PlayerReplaced(id, old, player));
+        }
+    }
+
+    /// Send chat message in the room, broadcast to players (and viewers)
+    pub async fn chat_send(
+        &mut self,
+        id: RoomId,
+        peer: PeerId,
+        text: String,
+        send: &Sender<SendMessage>,
+    ) -> Result<()> {
+        let players_only = self.config.chat_players_only;
+        let hr = self.rooms.get_mut(&id).ok_or(Error::NoRoom)?;
+        if !can_chat(&hr.room, &peer, players_only) {
+            return Err(Error::NoPlayer);
+        }
+        if text.is_empty() || text.len() > self.config.chat_size {
+            return Err(Error::PayloadTooLarge);
+        }
+
+        let at = status_now();
+        hr.chat.push_back((peer, text.clone(), at));
+        while hr.chat.len() > self.config.chat_history {
+            hr.chat.pop_front();
+        }
+
+        let message = SystemMessage::Chat(peer, text, at).to_values();
+        let p2p_bytes = message.to_bytes();
+        let rpc_msg = build_rpc_response(0, id, message.to_value());
+        for (p, _) in hr.room.iter() {
+            if !players_only || hr.room.is_player(p) {
+                let (bytes, msg) = (p2p_bytes.clone(), rpc_msg.clone());
+                send_peer(&hr.room, *p, bytes, msg, None, send, &self.metrics).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the chat history of the room
+    pub fn chat_history(&self, id: RoomId, peer: PeerId) -> Result<SystemMessage> {
+        let hr = self.rooms.get(&id).ok_or(Error::NoRoom)?;
+        if !can_chat(&hr.room, &peer, self.config.chat_players_only) {
+            return Err(Error::NoPlayer);
+        }
+        Ok(SystemMessage::ChatHistory(hr.chat.iter().cloned().collect()))
+    }
+
+    /// Broadcast the presence changes to the rooms, without game handler
+    async fn flush_presence(&mut self, send: &Sender<SendMessage>) {
+        for (rid, peer, online) in std::mem::take(&mut self.presence) {
+            if let Some(hr) = self.rooms.get(&rid) {
+                let viewers = hr.room.viewers().count();
+                let message = SystemMessage::Presence(peer, online, viewers).to_values();
+                broadcast(&hr.room, &message, send, None, 0, &self.metrics).await;
+            }
+        }
+    }
+
+    /// Send the snapshot of visible state to the late viewer
+    pub async fn send_snapshot(&self, id: RoomId, peer: PeerId, send: &Sender<SendMessage>) {
+        if let Some(hr) = self.rooms.get(&id) {
+            let handler = hr.handler.lock().await;
+            let snapshot = handler.snapshot().map(|p| (handler.encode(&p), p));
+            drop(handler);
+            if let Some((p2p_bytes, param)) = snapshot {
+                let rpc_msg = build_rpc_response(0, id, param.to_value());
+                send_peer(&hr.room, peer, p2p_bytes, rpc_msg, None, send, &self.metrics).await;
+            }
+        }
+    }
+
+    /// Attach the bots which handler configured to seats
+    async fn attach_bots(&mut self, id: RoomId) {
+        let bots = match self.rooms.get(&id) {
+            Some(hr) => hr.handler.lock().await.take_bots(),
+            None => return,
+        };
+        self.add_bots(id, bots).await;
+    }
+
+    /// Add the bots to seats, new seats will be joined when room not started
+    async fn add_bots(&mut self, id: RoomId, bots: Bots<H::Param>) {
+        if let Some(hr) = self.rooms.get_mut(&id) {
+            for (player, bot) in bots {
+                if !hr.room.is_player(&player.peer) {
+                    if !hr.room.join(player.peer) {
+                        warn!("Engine: bot {:?} cannot join room {}", player.peer, id);
+                        continue;
+                    }
+                    hr.context.write().await.players.push(player);
+                    let _ = self.events.send(EngineEvent::PlayerJoined(id, player));
+                }
+
+                let (sender, task) = spawn_bot(
+                    id,
+                    player.peer,
+                    bot,
+                    hr.handler.clone(),
+                    hr.context.clone(),
+                    self.task_sender.clone(),
+                );
+                hr.bots.insert(player.peer, sender);
+                hr.tasks.push(task);
+            }
         }
     }
 
+    /// The rooms not started and waiting players too long, need fill with bots
+    fn unfilled_rooms(&self) -> Vec<RoomId> {
+        if self.config.bot_fill == 0 {
+            return vec![];
+        }
+        let timeout = Duration::from_secs(self.config.bot_fill);
+        self.rooms
+            .iter()
+            .filter(|(_, hr)| {
+                !hr.filled && !hr.room.is_started() && hr.created.elapsed() > timeout
+            })
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Remove the room from the peer's online rooms
+    async fn remove_online(&self, peer: PeerId, id: RoomId) {
+        let mut onlines_lock = self.onlines.lock().await;
+        if let Some(rooms) = onlines_lock.get_mut(&peer) {
+            rooms.retain(|rid| *rid != id);
+            if rooms.is_empty() {
+                onlines_lock.remove(&peer);
+            }
+        }
+    }
+
+    /// Close the room without settlement, and clear the group
+    async fn close_room(&mut self, id: RoomId, send: &Sender<SendMessage>) {
+        if let Some(hr) = self.rooms.get(&id) {
+            let notice = MethodValues::new("over", vec![]);
+            broadcast(&hr.room, &notice, send, None, 0, &self.metrics).await;
+            let _ = send
+                .send(SendMessage::Network(NetworkType::DelGroup(id)))
+                .await;
+        }
+        self.over_room(id).await;
+        self.del_pending(id);
+        self.transit(id, RoomStatus::Expired);
+    }
+
+    /// Update the scanned block height of chain,
+    /// the pending rooms created before it was known use it as created height
+    fn update_height(&mut self, chain: usize, height: u64) {
+        self.heights.insert(chain, height);
+        for (id, proom) in self.pending.iter_mut() {
+            if proom.height.is_none() && split_room_id(*id).0 == chain {
+                proom.height = Some(height);
+            }
+        }
+    }
+
+    /// The pending rooms which not started or accepted before ttl or blocks
+    fn expired_pendings(&self) -> Vec<RoomId> {
+        let (ttl, blocks) = (self.config.pending_ttl, self.config.pending_blocks);
+        if ttl == 0 && blocks == 0 {
+            return vec![];
+        }
+        self.pending
+            .iter()
+            .filter(|(id, proom)| {
+                let aged = ttl > 0 && proom.created.elapsed() > Duration::from_secs(ttl);
+                let height = self.heights.get(&split_room_id(**id).0);
+                let passed = match (proom.height, height) {
+                    (Some(created), Some(height)) => blocks > 0 && *height > created + blocks,
+                    _ => false,
+                };
+                (aged || passed)
+                    && matches!(
+                        self.statuses.get(id).map(|s| s.status),
+                        Some(RoomStatus::Pending | RoomStatus::Accepting)
+                    )
+            })
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// The running rooms without any player activity before timeout
+    fn idle_rooms(&self) -> Vec<RoomId> {
+        if self.config.room_idle == 0 {
+            return vec![];
+        }
+        let timeout = Duration::from_secs(self.config.room_idle);
+        self.rooms
+            .iter()
+            .filter(|(id, hr)| {
+                hr.active.elapsed() > timeout
+                    && matches!(
+                        self.statuses.get(id).map(|s| s.status),
+                        Some(RoomStatus::Running)
+                    )
+            })
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Check the message size & rate limits of the peer in the room
+    pub fn check_limit(&mut self, id: RoomId, peer: PeerId, size: usize) -> Result<()> {
+        self.limiter.check(id, peer, size)?;
+        // every accepted message from peers is the room activity, not the limited ones
+        if let Some(hr) = self.rooms.get_mut(&id) {
+            hr.active = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Check if the peer breached the limits too many times
+    pub fn is_offender(&self, id: RoomId, peer: &PeerId) -> bool {
+        self.limiter.is_offender(id, peer)
+    }
+
+    /// Disconnect the peer from the room, return true if it was connected
+    pub async fn kick(&mut self, id: RoomId, peer: PeerId) -> bool {
+        let is_ok = if let Some(hr) = self.rooms.get_mut(&id) {
+            let connected = !matches!(hr.room.get(&peer), ConnectType::None);
+            hr.room.offline(peer);
+            connected
+        } else {
+            false
+        };
+        if is_ok {
+            let _ = self.events.send(EngineEvent::PlayerOffline(id, peer));
+            self.presence.push((id, peer, false));
+        }
+
+        let mut onlines_lock = self.onlines.lock().await;
+        if let Some(rooms) = onlines_lock.get_mut(&peer) {
+            vec_remove_item(rooms, &id);
+            if rooms.is_empty() {
+                onlines_lock.remove(&peer);
+            }
+        }
+
+        is_ok
+    }
+
+    /// Disconnect the peer from the room, and the handler offline when it is a player
+    pub async fn kick_offline(
+        &mut self,
+        id: RoomId,
+        peer: PeerId,
+    ) -> Result<Option<HandleResult<H::Param>>> {
+        if !self.kick(id, peer).await {
+            return Ok(None);
+        }
+
+        let hr = self.get_room(&id);
+        if !hr.room.is_player(&peer) {
+            return Ok(None);
+        }
+        let ctx = hr.context.read().await.clone();
+        let mut handler = hr.handler.lock().await;
+        let res = handler.offline(&ctx, peer).await?;
+        Ok(Some(res))
+    }
+
     /// Check room exists
     pub fn has_room(&self, id: &RoomId) -> bool {
         self.rooms.contains_key(id)
@@ -217,6 +840,7 @@ impl<H: Handler> Engine<H> {
     /// When a player online/connected
     pub async fn online(&mut self, id: RoomId, peer: PeerId, ctype: ConnectType) -> bool {
         let is_ok = if let Some(hr) = self.rooms.get_mut(&id) {
+            hr.active = Instant::now();
             hr.room.online(peer, ctype)
         } else {
             false
@@ -232,6 +856,8 @@ impl<H: Handler> Engine<H> {
                     }
                 })
                 .or_insert(vec![id]);
+            let _ = self.events.send(EngineEvent::PlayerOnline(id, peer));
+            self.presence.push((id, peer, true));
         }
 
         is_ok
@@ -244,8 +870,154 @@ impl<H: Handler> Engine<H> {
             for rid in rooms {
                 if let Some(hr) = self.rooms.get_mut(&rid) {
                     hr.room.offline(peer);
+                    let _ = self.events.send(EngineEvent::PlayerOffline(rid, peer));
+                    self.presence.push((rid, peer, false));
+                }
+            }
+        }
+    }
+
+    /// When a websocket connection is closed or idle timeout,
+    /// offline the peer in the rooms which connected with it
+    pub async fn offline_rpc(&mut self, uid: u64) -> Vec<(RoomId, PeerId)> {
+        let mut offlines = vec![];
+        let peer = if let Some((peer, _)) = self.heartbeats.remove(&uid) {
+            peer
+        } else {
+            return offlines;
+        };
+
+        let mut onlines_lock = self.onlines.lock().await;
+        if let Some(rooms) = onlines_lock.get_mut(&peer) {
+            rooms.retain(|rid| {
+                if let Some(hr) = self.rooms.get_mut(rid) {
+                    if let ConnectType::Rpc(cid) = hr.room.get(&peer) {
+                        if cid == uid {
+                            hr.room.offline(peer);
+                            offlines.push((*rid, peer));
+                            let _ = self.events.send(EngineEvent::PlayerOffline(*rid, peer));
+                            self.presence.push((*rid, peer, false));
+                            return false;
+                        }
+                    }
+                    true
+                } else {
+                    false
+                }
+            });
+            if rooms.is_empty() {
+                onlines_lock.remove(&peer);
+            }
+        }
+
+        offlines
+    }
+
+    /// Refresh the websocket connection active time
+    pub fn heartbeat(&mut self, uid: u64, peer: PeerId) {
+        if self.config.idle_timeout == 0 {
+            return;
+        }
+        self.heartbeats.insert(uid, (peer, Instant::now()));
+    }
+
+    /// Get the websocket connections which is idle timeout
+    pub fn idle_heartbeats(&self) -> Vec<u64> {
+        if self.config.idle_timeout == 0 {
+            return vec![];
+        }
+
+        let timeout = Duration::from_secs(self.config.idle_timeout);
+        self.heartbeats
+            .iter()
+            .filter_map(|(uid, (_, time))| {
+                if time.elapsed() > timeout {
+                    Some(*uid)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Send the handle result to the room, and prove the room when it is over
+    async fn send_result(
+        &mut self,
+        rid: RoomId,
+        mut res: HandleResult<H::Param>,
+        send: &Sender<SendMessage>,
+        rpc: Option<(PeerId, u64)>,
+        id: u64,
+        chain_send: &UnboundedSender<ChainMessage>,
+    ) {
+        let replace = std::mem::take(&mut res.replace);
+        if !replace.is_empty() {
+            for (old, player) in replace {
+                self.replace_player(rid, old, player).await;
+            }
+            self.attach_bots(rid).await;
+        }
+
+        if let Some(hr) = self.rooms.get_mut(&rid) {
+            deliver_bots(&hr.bots, &res);
+            let is_over = res.over;
+            // lock the room when started, and notify players & viewers
+            if res.started && hr.room.start() {
+                let notice = MethodValues::new("started", vec![]);
+                broadcast(&hr.room, &notice, send, None, 0, &self.metrics).await;
+                let _ = self.events.send(EngineEvent::RoomLocked(rid));
+            }
+            // p2p bytes and projection of the broadcast messages for viewers, by the handler
+            let (bytes, views) = if !res.one.is_empty() || !res.all.is_empty() {
+                let handler = hr.handler.lock().await;
+                let one = res.one.iter().map(|(_, p)| handler.encode(p)).collect();
+                let all = res.all.iter().map(|p| handler.encode(p)).collect();
+                let views: Vec<(Vec<u8>, H::Param)> = if hr.room.has_viewers() {
+                    res.all
+                        .iter()
+                        .filter_map(|p| handler.view(p))
+                        .map(|p| (handler.encode(&p), p))
+                        .collect()
+                } else {
+                    vec![]
+                };
+                ((one, all), views)
+            } else {
+                ((vec![], vec![]), vec![])
+            };
+            handle_result(&hr.room, res, bytes, send, rpc, id, &self.metrics).await;
+
+            if !views.is_empty() {
+                let messages = viewer_messages(&hr.room, views, &self.metrics);
+                if self.config.viewer_delay == 0 {
+                    for msg in messages {
+                        send.send(msg).await.expect("TDN channel closed");
+                    }
+                } else {
+                    if hr.delay.is_none() {
+                        let (sender, task) = spawn_delay(send.clone());
+                        hr.delay = Some(sender);
+                        hr.tasks.push(task);
+                    }
+                    if let Some(delay) = &hr.delay {
+                        let due = Instant::now() + Duration::from_secs(self.config.viewer_delay);
+                        let _ = delay.send((due, messages));
+                    }
                 }
             }
+
+            if is_over {
+                if let Some(lifecycle) = self.statuses.get_mut(&rid) {
+                    lifecycle.transit(RoomStatus::Proving);
+                }
+                handle_over(
+                    rid,
+                    hr.handler.clone(),
+                    chain_send.clone(),
+                    self.metrics.clone(),
+                    self.events.clone(),
+                );
+            }
         }
     }
 
@@ -257,14 +1029,27 @@ impl<H: Handler> Engine<H> {
 
     /// Run the engine with game logic and channel
     pub async fn run_with_channel(
+        self,
+        chain_send: UnboundedSender<ChainMessage>,
+        chain_recv: UnboundedReceiver<ChainMessage>,
+    ) -> Result<()> {
+        let chains = self.config.to_chains().await?;
+        self.run_with_chains(chains, chain_send, chain_recv).await
+    }
+
+    /// Run the engine with the scanners & pools of chains, and channel
+    pub(crate) async fn run_with_chains(
         mut self,
+        chains: Vec<ChainParams>,
         chain_send: UnboundedSender<ChainMessage>,
         mut chain_recv: UnboundedReceiver<ChainMessage>,
     ) -> Result<()> {
         let (tdn_config, key) = self.config.to_tdn();
-        let chain_option = self.config.to_chain().await;
 
-        let (peer_addr, send, mut out_recv) = start_with_config_and_key(tdn_config, key).await?;
+        let (peer_addr, send, mut out_recv) = match self.transport.take() {
+            Some(transport) => transport,
+            None => start_with_config_and_key(tdn_config, key).await?,
+        };
         println!("SERVER: peer id: {:?}", peer_addr);
         println!("P2P   : http://0.0.0.0:{}", self.config.p2p_port);
         println!("HTTP  : http://0.0.0.0:{}", self.config.http_port);
@@ -272,21 +1057,80 @@ impl<H: Handler> Engine<H> {
             println!("WS    : ws://0.0.0.0:{}", p);
         }
 
-        let (pool_send, pool_recv) = pool_channel();
-        if let Some((scan_providers, pool_provider, market_address, start_block)) = chain_option {
-            let send1 = chain_send.clone();
-            let send2 = chain_send.clone();
+        let mut command_recv = self
+            .command_receiver
+            .take()
+            .expect("Engine is already running");
+        let signal_send = self.command_sender.clone();
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            info!("Engine: received shutdown signal");
+            let _ = signal_send.send(EngineCommand::Shutdown);
+        });
+
+        for observer in self.observers.drain(..) {
+            tokio::spawn(observe(observer, self.events.subscribe()));
+        }
+
+        if let Some(port) = self.config.metrics_port {
+            println!("METRICS: http://0.0.0.0:{}/metrics", port);
+            tokio::spawn(metrics_listen(port, self.metrics.clone()));
+        }
+
+        // every chain market has its own scanner and pool, by the namespace of rooms
+        let mut pool_sends = HashMap::new();
+        for chain in chains {
+            println!(
+                "CHAIN {}: {} {:?}",
+                chain.namespace,
+                chain.network.to_str(),
+                chain.market
+            );
+            let (pool_send, pool_recv) = pool_channel();
+            let scan = ScanConfig {
+                chain: chain.namespace,
+                clients: chain.providers,
+                ws_urls: chain.ws_urls,
+                market: chain.market,
+                start: chain.start_block,
+                deploy: chain.deploy_block,
+                confirmations: chain.confirmations,
+                quorum: self.config.chain_quorum,
+            };
             tokio::spawn(scan_listen(
-                scan_providers,
-                market_address,
-                send1,
-                start_block,
+                scan,
+                chain_send.clone(),
+                self.storage.clone(),
+                self.metrics.clone(),
+            ));
+            let pool = PoolConfig {
+                client: chain.signer,
+                market: chain.market,
+            };
+            tokio::spawn(pool_listen(
+                pool,
+                chain_send.clone(),
+                pool_recv,
+                self.pool_status.clone(),
+                self.metrics.clone(),
+                self.events.clone(),
+                self.storage.clone(),
             ));
-            tokio::spawn(pool_listen(pool_provider, market_address, send2, pool_recv));
+            pool_sends.insert(chain.namespace, pool_send);
         }
 
-        let (task_sender, mut task_receiver) = unbounded_channel();
+        let task_sender = self.task_sender.clone();
+        let mut task_receiver = self
+            .task_receiver
+            .take()
+            .expect("Engine is already running");
+        let mut heartbeat = interval(Duration::from_secs(self.config.heartbeat_interval.max(1)));
+        let grace = Duration::from_secs(self.config.shutdown_timeout);
+        let mut shutdown: Option<Instant> = None;
+        let mut forced = false;
         loop {
+            self.flush_presence(&send).await;
+
             let work = select! {
                 w = async {
                     chain_recv.recv().await.map(FutureMessage::Chain)
@@ -297,52 +1141,180 @@ impl<H: Handler> Engine<H> {
                 w = async {
                     task_receiver.recv().await.map(FutureMessage::Task)
                 } => w,
+                w = async {
+                    heartbeat.tick().await;
+                    Some(FutureMessage::Heartbeat)
+                } => w,
+                w = async {
+                    command_recv.recv().await.map(FutureMessage::Command)
+                } => w,
             };
 
             match work {
                 Some(FutureMessage::Task(message)) => match message {
                     TaskMessage::Result(rid, res) => {
-                        let is_over = res.over;
-                        handle_result(&self.get_room(&rid).room, res, &send, None, 0).await;
-                        if is_over {
-                            handle_over(
-                                rid,
-                                self.get_room(&rid).handler.clone(),
-                                chain_send.clone(),
-                            );
+                        self.send_result(rid, res, &send, None, 0, &chain_send).await;
+                    }
+                },
+                Some(FutureMessage::Command(command)) => match command {
+                    EngineCommand::CreateRoom(rid, game, viewable, players, params, tx) => {
+                        if shutdown.is_some() {
+                            let _ = tx.send(Err(Error::Anyhow("engine is shutting down".into())));
+                            continue;
+                        }
+                        if players.is_empty() || !is_local_room(rid) || self.has_room(&rid) {
+                            let _ = tx.send(Err(Error::Params));
+                            continue;
+                        }
+                        if !self.games.contains_key(&game) {
+                            let _ = tx.send(Err(Error::NoGame));
+                            continue;
+                        }
+                        if !self.registry.validate(&game, &players) {
+                            let _ = tx.send(Err(Error::Auth));
+                            continue;
+                        }
+                        info!("Engine: local new room: {}", rid);
+                        // rooms without chain use local random seed
+                        let now = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map(|d| d.as_nanos())
+                            .unwrap_or(0);
+                        let salt = keccak256([&rid.to_le_bytes()[..], &now.to_le_bytes()].concat());
+                        self.create_pending(rid, game, viewable, players[0], salt, [0u8; 32]);
+                        for player in players.into_iter().skip(1) {
+                            self.join_pending(rid, player);
+                        }
+                        let sequencer = (peer_addr, String::new());
+                        self.start_room(rid, sequencer, params, true, task_sender.clone())
+                            .await;
+                        if self.has_room(&rid) {
+                            let _ = send
+                                .send(SendMessage::Network(NetworkType::AddGroup(rid)))
+                                .await;
+                            let _ = tx.send(Ok(()));
+                        } else {
+                            let _ = tx.send(Err(Error::NoRoom));
+                        }
+                    }
+                    EngineCommand::CloseRoom(rid) => {
+                        self.close_room(rid, &send).await;
+                    }
+                    EngineCommand::Inject(rid, peer, param) => {
+                        if !self.has_room(&rid) {
+                            continue;
+                        }
+                        let hr = self.get_room(&rid);
+                        let ctx = hr.context.read().await.clone();
+                        let mut handler = hr.handler.lock().await;
+                        let res = handler.handle(&ctx, peer, param).await;
+                        drop(handler);
+
+                        if let Ok(res) = res {
+                            self.send_result(rid, res, &send, None, 0, &chain_send).await;
+                        }
+                    }
+                    EngineCommand::QueryRoom(rid, tx) => {
+                        let _ = tx.send(self.room_info(&rid));
+                    }
+                    EngineCommand::Shutdown => {
+                        if shutdown.is_none() {
+                            info!("Engine: shutdown, draining {} rooms", self.rooms.len());
+                            shutdown = Some(Instant::now() + grace);
+                            let notice =
+                                MethodValues::new("shutdown", vec![grace.as_secs().into()]);
+                            for hr in self.rooms.values() {
+                                broadcast(&hr.room, &notice, &send, None, 0, &self.metrics).await;
+                            }
                         }
                     }
                 },
+                Some(FutureMessage::Heartbeat) => {
+                    self.prune_statuses();
+                    for rid in self.unfilled_rooms() {
+                        let hr = self.rooms.get_mut(&rid).unwrap(); // safe before check
+                        hr.filled = true;
+                        let ctx = hr.context.read().await.clone();
+                        let bots = hr.handler.lock().await.fill_seats(&ctx).await;
+                        debug!("Engine: fill room {} with {} bots", rid, bots.len());
+                        self.add_bots(rid, bots).await;
+                    }
+                    for rid in self.expired_pendings() {
+                        debug!("Engine: pending room {} expired", rid);
+                        self.del_pending(rid);
+                        self.transit(rid, RoomStatus::Expired);
+                    }
+                    for rid in self.idle_rooms() {
+                        debug!("Engine: room {} idle timeout", rid);
+                        let hr = self.get_room(&rid);
+                        let ctx = hr.context.read().await.clone();
+                        let mut handler = hr.handler.lock().await;
+                        let res = handler.on_idle(&ctx).await;
+                        drop(handler);
+
+                        match res {
+                            Ok(res) if res.over => {
+                                self.send_result(rid, res, &send, None, 0, &chain_send).await;
+                            }
+                            _ => self.close_room(rid, &send).await,
+                        }
+                    }
+                    for uid in self.idle_heartbeats() {
+                        for (rid, peer) in self.offline_rpc(uid).await {
+                            debug!("Engine: websocket {} idle timeout in room {}", uid, rid);
+                            let hr = self.get_room(&rid);
+                            let ctx = hr.context.read().await.clone();
+                            let is_player = hr.room.is_player(&peer);
+                            let mut handler = hr.handler.lock().await;
+                            let res = if is_player {
+                                handler.offline(&ctx, peer).await
+                            } else {
+                                handler.viewer_offline(&ctx, peer).await
+                            };
+                            drop(handler);
+
+                            if let Ok(res) = res {
+                                self.send_result(rid, res, &send, None, 0, &chain_send).await;
+                            }
+                        }
+                    }
+                }
                 Some(FutureMessage::Network(message)) => match message {
                     ReceiveMessage::Group(rid, msg) => {
+                        self.metrics.message_in(Transport::P2p);
                         if !self.has_room(&rid) {
                             continue;
                         }
                         if let Ok(Some(res)) = handle_p2p(&mut self, &send, rid, msg).await {
-                            let is_over = res.over;
-                            handle_result(&self.get_room(&rid).room, res, &send, None, 0).await;
-                            if is_over {
-                                handle_over(
-                                    rid,
-                                    self.get_room(&rid).handler.clone(),
-                                    chain_send.clone(),
-                                );
+                            self.send_result(rid, res, &send, None, 0, &chain_send).await;
+                        }
+                    }
+                    ReceiveMessage::Rpc(uid, params, is_ws)
+                        if params["method"]
+                            .as_str()
+                            .map(|m| m.starts_with("admin_"))
+                            .unwrap_or(false) =>
+                    {
+                        match handle_admin(&mut self, &send, uid, params, is_ws).await {
+                            Ok(Some((res, rid))) => {
+                                self.send_result(rid, res, &send, None, 0, &chain_send).await;
+                            }
+                            Ok(None) => {}
+                            Err(err) => {
+                                let msg = RpcError::Custom(format!("{:?}", err)).json(0);
+                                let _ = send.send(SendMessage::Rpc(uid, msg, is_ws)).await;
                             }
                         }
                     }
                     ReceiveMessage::Rpc(uid, params, is_ws) => {
+                        if is_ws {
+                            self.metrics.message_in(Transport::Ws);
+                        } else {
+                            self.metrics.message_in(Transport::Http);
+                        }
                         match handle_rpc(&mut self, &send, uid, params, is_ws).await {
                             Ok(Some((res, rid, is_rpc, id))) => {
-                                let is_over = res.over;
-                                handle_result(&self.get_room(&rid).room, res, &send, is_rpc, id)
-                                    .await;
-                                if is_over {
-                                    handle_over(
-                                        rid,
-                                        self.get_room(&rid).handler.clone(),
-                                        chain_send.clone(),
-                                    );
-                                }
+                                self.send_result(rid, res, &send, is_rpc, id, &chain_send).await;
                             }
                             Ok(None) => {
                                 let msg = RpcError::Custom("None".to_owned()).json(0);
@@ -360,47 +1332,46 @@ impl<H: Handler> Engine<H> {
                     ReceiveMessage::Own(..) => {}
                 },
                 Some(FutureMessage::Chain(message)) => match message {
-                    ChainMessage::CreateRoom(
-                        rid,
-                        game,
-                        viewable,
-                        account,
-                        peer,
-                        signer,
-                        salt,
-                        block,
-                    ) => {
+                    ChainMessage::CreateRoom(rid, game, viewable, player, salt, block) => {
                         info!("Engine: chain new room created !");
-                        self.create_pending(
-                            rid,
-                            game,
-                            viewable,
-                            Player {
-                                account,
-                                peer,
-                                signer,
-                            },
-                            salt,
-                            block,
-                        );
+                        self.create_pending(rid, game, viewable, player, salt, block);
                     }
-                    ChainMessage::JoinRoom(rid, account, peer, signer) => {
+                    ChainMessage::JoinRoom(rid, player) => {
                         info!("Engine: chain new player joined !");
-                        self.join_pending(
-                            rid,
-                            Player {
-                                account,
-                                peer,
-                                signer,
-                            },
-                        );
+                        self.join_pending(rid, player);
                     }
                     ChainMessage::StartRoom(rid, game) => {
                         // send accept operation to chain
                         // check room is exist
-                        if let Some(proom) = self.pending.get(&rid) {
-                            let params = H::chain_accept(&proom.players).await;
-                            let _ = pool_send.send(PoolMessage::AcceptRoom(rid, params));
+                        let accepting = self
+                            .room_status(&rid)
+                            .map(|s| s.status != RoomStatus::Pending)
+                            .unwrap_or(false);
+                        if shutdown.is_some() {
+                            debug!("Engine: shutting down, skip room {}", rid);
+                        } else if accepting {
+                            // rescan after reorg, the accept had been sent
+                            debug!("Engine: room {} already accepting", rid);
+                        } else if let Some(proom) = self.pending.get(&rid) {
+                            if !self.registry.validate(&proom.game, &proom.players) {
+                                warn!("Engine: room {} has invalid player keys, skip", rid);
+                            } else if self
+                                .policy
+                                .accept_with_sequencers(
+                                    rid,
+                                    proom.game,
+                                    &proom.players,
+                                    &self.sequencers,
+                                )
+                                .await
+                            {
+                                let params =
+                                    self.registry.accept(&proom.game, &proom.players).await;
+                                to_pool(&pool_sends, rid, PoolMessage::AcceptRoom(rid, params));
+                                self.transit(rid, RoomStatus::Accepting);
+                            } else {
+                                debug!("Engine: policy rejected room {}", rid);
+                            }
                         } else if self.games.contains_key(&game) {
                             // TODO fetch room from chain.
                         }
@@ -409,121 +1380,236 @@ impl<H: Handler> Engine<H> {
                         info!("Engine: start new room: {}", rid);
                         // if mine, create room
                         let is_own = sequencer == peer_addr;
-                        self.start_room(rid, (sequencer, ws), params, is_own, task_sender.clone())
+                        let _ = self.events.send(EngineEvent::RoomAccepted(rid, sequencer));
+                        self.sequencers.accept(rid, sequencer, ws.clone());
+                        if shutdown.is_some() {
+                            // draining rooms only, the accepted room cannot be served
+                            if is_own {
+                                warn!("Engine: shutting down, not start accepted room {}", rid);
+                            }
+                        } else {
+                            self.start_room(
+                                rid,
+                                (sequencer, ws),
+                                params,
+                                is_own,
+                                task_sender.clone(),
+                            )
                             .await;
 
-                        if is_own {
-                            let _ = send
-                                .send(SendMessage::Network(NetworkType::AddGroup(rid)))
-                                .await;
+                            if is_own {
+                                let _ = send
+                                    .send(SendMessage::Network(NetworkType::AddGroup(rid)))
+                                    .await;
+                            }
                         }
                     }
                     ChainMessage::GameOverRoom(gid, data, proof) => {
-                        let _ = pool_send.send(PoolMessage::OverRoom(gid, data, proof));
+                        if is_local_room(gid) {
+                            // local rooms have no chain settlement
+                            self.transit(gid, RoomStatus::Settled);
+                        } else {
+                            to_pool(&pool_sends, gid, PoolMessage::OverRoom(gid, data, proof));
+                            self.transit(gid, RoomStatus::Submitting);
+                        }
+                        if self.has_room(&gid) {
+                            let _ = send
+                                .send(SendMessage::Network(NetworkType::DelGroup(gid)))
+                                .await;
+                        }
                         self.over_room(gid).await;
                     }
                     ChainMessage::ChainOverRoom(gid) => {
+                        self.sequencers.over(gid);
                         self.del_pending(gid);
+                        self.transit(gid, RoomStatus::Settled);
+                        to_pool(&pool_sends, gid, PoolMessage::Submitted(gid));
                     }
-                    ChainMessage::Reprove => {
+                    ChainMessage::Reprove(gid) => {
+                        self.transit(gid, RoomStatus::Failed);
                         // TODO logic
                     }
+                    ChainMessage::StakeSequencer(chain, peer, http, ws, staking) => {
+                        info!("Engine: sequencer {:?} staked {} in {}", peer, staking, chain);
+                        self.sequencers.stake(chain, peer, http, ws, staking);
+                    }
+                    ChainMessage::UnstakeSequencer(chain, peer, staking) => {
+                        info!(
+                            "Engine: sequencer {:?} unstaked to {} in {}",
+                            peer, staking, chain
+                        );
+                        self.sequencers.unstake(chain, peer, staking);
+                    }
+                    ChainMessage::ClaimRoom(rid) => {
+                        self.sequencers.over(rid);
+                    }
+                    ChainMessage::RevertCreateRoom(rid) => {
+                        warn!("Engine: room {} reverted by reorg", rid);
+                        if !self.has_room(&rid) {
+                            self.del_pending(rid);
+                            self.statuses.remove(&rid);
+                        }
+                    }
+                    ChainMessage::RevertJoinRoom(rid, peer) => {
+                        warn!("Engine: player {:?} of room {} reverted by reorg", peer, rid);
+                        self.leave_pending(rid, &peer);
+                    }
+                    ChainMessage::Height(chain, height) => {
+                        self.update_height(chain, height);
+                    }
                 },
                 None => break,
             }
+
+            if let Some(deadline) = shutdown {
+                if self.rooms.is_empty() {
+                    break;
+                }
+                let now = Instant::now();
+                if now > deadline + grace {
+                    warn!("Engine: shutdown timeout, {} rooms lost", self.rooms.len());
+                    break;
+                }
+                if now > deadline && !forced {
+                    // force over the rooms which not finished, and prove them
+                    forced = true;
+                    let rids: Vec<RoomId> = self.rooms.keys().copied().collect();
+                    for rid in rids {
+                        let mut res = HandleResult::default();
+                        res.over();
+                        self.send_result(rid, res, &send, None, 0, &chain_send).await;
+                    }
+                }
+            }
+        }
+
+        if shutdown.is_some() {
+            // waiting pools send all over transactions
+            for pool_send in pool_sends.values() {
+                let (notify, waiting) = oneshot::channel();
+                if pool_send.send(PoolMessage::Flush(notify)).is_ok() {
+                    let _ = timeout(grace, waiting).await;
+                }
+            }
+            info!("Engine: shutdown completed");
         }
 
         Ok(())
     }
 }
 
+impl Engine<Games> {
+    /// Register the game handler, so one engine can host different games with their own params
+    pub fn register_game<G: Handler>(&mut self, game: GameId) {
+        self.games.entry(game).or_default();
+        self.registry.register_game::<G>(game);
+    }
+}
+
+/// Waiting the terminate or interrupt signal
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut term) = signal(SignalKind::terminate()) {
+            select! {
+                _ = term.recv() => {},
+                _ = tokio::signal::ctrl_c() => {},
+            }
+            return;
+        }
+    }
+
+    let _ = tokio::signal::ctrl_c().await;
+}
+
 enum FutureMessage<H: Handler> {
     Network(ReceiveMessage),
     Chain(ChainMessage),
     Task(TaskMessage<H>),
+    Heartbeat,
+    Command(EngineCommand<H::Param>),
+}
+
+/// Send the message to the pool of room's chain market
+fn to_pool(pools: &HashMap<usize, UnboundedSender<PoolMessage>>, rid: RoomId, msg: PoolMessage) {
+    let (chain, _) = split_room_id(rid);
+    if let Some(pool) = pools.get(&chain) {
+        let _ = pool.send(msg);
+    } else {
+        warn!("Engine: no chain {} for room {}", chain, rid);
+    }
+}
+
+/// Check the peer can send & receive chat messages in the room
+fn can_chat(room: &Room, peer: &PeerId, players_only: bool) -> bool {
+    if room.is_player(peer) {
+        true
+    } else {
+        !players_only && !matches!(room.get(peer), ConnectType::None)
+    }
+}
+
+/// Deliver the messages to bots in seats
+fn deliver_bots<P: Param>(bots: &HashMap<PeerId, UnboundedSender<P>>, result: &HandleResult<P>) {
+    if bots.is_empty() {
+        return;
+    }
+    for (peer, param) in result.one.iter() {
+        if let Some(bot) = bots.get(peer) {
+            if let Ok(param) = P::from_bytes(param.to_bytes()) {
+                let _ = bot.send(param);
+            }
+        }
+    }
+    for param in result.all.iter() {
+        let bytes = param.to_bytes();
+        for bot in bots.values() {
+            if let Ok(param) = P::from_bytes(bytes.clone()) {
+                let _ = bot.send(param);
+            }
+        }
+    }
 }
 
-/// Handle result
+/// Handle result, with the p2p bytes of one & all messages
 async fn handle_result<P: Param>(
     room: &Room,
     result: HandleResult<P>,
+    bytes: (Vec<Vec<u8>>, Vec<Vec<u8>>),
     send: &Sender<SendMessage>,
     rpc: Option<(PeerId, u64)>,
     id: u64,
+    metrics: &Metrics,
 ) {
     let HandleResult {
-        mut all,
-        mut one,
+        all,
+        one,
         over,
         started: _,
+        replace: _,
     } = result;
 
-    loop {
-        if !one.is_empty() {
-            let (peer, params) = one.remove(0);
-            let p2p_bytes = params.to_bytes();
-            let rpc_msg = build_rpc_response(id, room.id, params.to_value());
-            match room.get(&peer) {
-                ConnectType::P2p => send
-                    .send(SendMessage::Group(
-                        room.id,
-                        SendType::Event(0, peer, p2p_bytes),
-                    ))
-                    .await
-                    .expect("TDN channel closed"),
-                ConnectType::Rpc(uid) => send
-                    .send(SendMessage::Rpc(uid, rpc_msg, true))
-                    .await
-                    .expect("TDN channel closed"),
-                ConnectType::None => {
-                    if let Some((p, uid)) = rpc {
-                        if p == peer {
-                            send.send(SendMessage::Rpc(uid, rpc_msg, false))
-                                .await
-                                .expect("TDN channel closed");
-                        }
-                    }
-                }
-            }
-        } else {
-            break;
-        }
-    }
-
-    loop {
-        if !all.is_empty() {
-            let params = all.remove(0);
-            let p2p_bytes = params.to_bytes();
-            let rpc_msg = build_rpc_response(id, room.id, params.to_value());
-            for (peer, c) in room.iter() {
-                match c {
-                    ConnectType::P2p => {
-                        send.send(SendMessage::Group(
-                            room.id,
-                            SendType::Event(0, *peer, p2p_bytes.clone()),
-                        ))
-                        .await
-                        .expect("TDN channel closed");
-                    }
-                    ConnectType::Rpc(uid) => {
-                        send.send(SendMessage::Rpc(*uid, rpc_msg.clone(), true))
-                            .await
-                            .expect("TDN channel closed");
-                    }
-                    ConnectType::None => {
-                        if let Some((p, uid)) = rpc {
-                            if p == *peer {
-                                send.send(SendMessage::Rpc(uid, rpc_msg.clone(), false))
-                                    .await
-                                    .expect("TDN channel closed");
-                            }
-                        }
-                    }
-                }
-            }
-            // TODO
-        } else {
-            break;
+    let (one_bytes, all_bytes) = bytes;
+    for ((peer, params), p2p_bytes) in one.into_iter().zip(one_bytes) {
+        let rpc_msg = build_rpc_response(id, room.id, params.to_value());
+        send_peer(room, peer, p2p_bytes, rpc_msg, rpc, send, metrics).await;
+    }
+
+    // viewers will receive the projection of broadcast messages
+    for (params, p2p_bytes) in all.into_iter().zip(all_bytes) {
+        let rpc_msg = build_rpc_response(id, room.id, params.to_value());
+        for (peer, _) in room.iter().filter(|(p, _)| room.is_player(p)) {
+            send_peer(
+                room,
+                *peer,
+                p2p_bytes.clone(),
+                rpc_msg.clone(),
+                rpc,
+                send,
+                metrics,
+            )
+            .await;
         }
     }
 
@@ -532,44 +1618,135 @@ async fn handle_result<P: Param>(
             method: "over".to_owned(),
             params: vec![],
         };
-        let p2p_bytes = params.to_bytes();
-        let rpc_msg = build_rpc_response(id, room.id, params.to_value());
-        for (peer, c) in room.iter() {
-            match c {
-                ConnectType::P2p => send
-                    .send(SendMessage::Group(
-                        room.id,
-                        SendType::Event(0, *peer, p2p_bytes.clone()),
-                    ))
-                    .await
-                    .expect("TDN channel closed"),
-                ConnectType::Rpc(uid) => {
-                    send.send(SendMessage::Rpc(*uid, rpc_msg.clone(), true))
-                        .await
-                        .expect("TDN channel closed");
-                }
-                ConnectType::None => {
-                    if let Some((p, uid)) = rpc {
-                        if p == *peer {
-                            send.send(SendMessage::Rpc(uid, rpc_msg.clone(), false))
-                                .await
-                                .expect("TDN channel closed");
-                        }
-                    }
-                }
+        broadcast(room, &params, send, rpc, id, metrics).await;
+    }
+}
+
+/// Broadcast engine message to all players/viewers in the room
+async fn broadcast(
+    room: &Room,
+    params: &MethodValues,
+    send: &Sender<SendMessage>,
+    rpc: Option<(PeerId, u64)>,
+    id: u64,
+    metrics: &Metrics,
+) {
+    let p2p_bytes = params.to_bytes();
+    let rpc_msg = build_rpc_response(id, room.id, params.to_value());
+    for (peer, _) in room.iter() {
+        send_peer(
+            room,
+            *peer,
+            p2p_bytes.clone(),
+            rpc_msg.clone(),
+            rpc,
+            send,
+            metrics,
+        )
+        .await;
+    }
+}
+
+/// Build the viewers messages of broadcast projections
+fn viewer_messages<P: Param>(
+    room: &Room,
+    views: Vec<(Vec<u8>, P)>,
+    metrics: &Metrics,
+) -> Vec<SendMessage> {
+    let mut messages = vec![];
+    for (p2p_bytes, params) in views {
+        let rpc_msg = build_rpc_response(0, room.id, params.to_value());
+        for (peer, _) in room.viewers() {
+            if let Some(msg) =
+                peer_message(room, *peer, p2p_bytes.clone(), rpc_msg.clone(), None, metrics)
+            {
+                messages.push(msg);
             }
         }
     }
+    messages
+}
+
+/// Delay queue of viewers messages, keep the messages order
+fn spawn_delay(
+    send: Sender<SendMessage>,
+) -> (UnboundedSender<(Instant, Vec<SendMessage>)>, AbortHandle) {
+    let (sender, mut receiver) = unbounded_channel::<(Instant, Vec<SendMessage>)>();
+    let task = tokio::spawn(async move {
+        while let Some((due, messages)) = receiver.recv().await {
+            sleep_until(due.into()).await;
+            for msg in messages {
+                let _ = send.send(msg).await;
+            }
+        }
+    });
+    (sender, task.abort_handle())
+}
+
+/// Send message to the player/viewer with its connect type
+async fn send_peer(
+    room: &Room,
+    peer: PeerId,
+    p2p_bytes: Vec<u8>,
+    rpc_msg: Value,
+    rpc: Option<(PeerId, u64)>,
+    send: &Sender<SendMessage>,
+    metrics: &Metrics,
+) {
+    if let Some(msg) = peer_message(room, peer, p2p_bytes, rpc_msg, rpc, metrics) {
+        send.send(msg).await.expect("TDN channel closed");
+    }
+}
+
+/// Build message to the player/viewer with its connect type
+fn peer_message(
+    room: &Room,
+    peer: PeerId,
+    p2p_bytes: Vec<u8>,
+    rpc_msg: Value,
+    rpc: Option<(PeerId, u64)>,
+    metrics: &Metrics,
+) -> Option<SendMessage> {
+    match room.get(&peer) {
+        ConnectType::P2p => {
+            metrics.message_out(Transport::P2p);
+            Some(SendMessage::Group(
+                room.id,
+                SendType::Event(0, peer, p2p_bytes),
+            ))
+        }
+        ConnectType::Rpc(uid) => {
+            metrics.message_out(Transport::Ws);
+            Some(SendMessage::Rpc(uid, rpc_msg, true))
+        }
+        ConnectType::None => match rpc {
+            Some((p, uid)) if p == peer => {
+                metrics.message_out(Transport::Http);
+                Some(SendMessage::Rpc(uid, rpc_msg, false))
+            }
+            _ => None,
+        },
+    }
 }
 
 fn handle_over<H: Handler>(
     rid: RoomId,
     handler: Arc<Mutex<H>>,
     chain_send: UnboundedSender<ChainMessage>,
+    metrics: Arc<Metrics>,
+    events: broadcast::Sender<EngineEvent>,
 ) {
     tokio::spawn(async move {
+        metrics.rooms_proving.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
         let mut lock = handler.lock().await;
-        if let Ok((data, proof)) = lock.prove().await {
+        let res = lock.prove().await;
+        drop(lock);
+        metrics.proof_duration.observe(start.elapsed());
+        metrics.rooms_proving.fetch_sub(1, Ordering::Relaxed);
+
+        if let Ok((data, proof)) = res {
+            let _ = events.send(EngineEvent::ProofProduced(rid));
             let _ = chain_send.send(ChainMessage::GameOverRoom(rid, data, proof));
         }
     });
@@ -609,3 +1786,109 @@ fn build_rpc_response(id: u64, gid: RoomId, params: Value) -> Value {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use z4_types::{local_room_id, Tasks};
+
+    struct Game;
+
+    #[async_trait::async_trait]
+    impl Handler for Game {
+        type Param = Value;
+
+        async fn chain_create(
+            _players: &[Player],
+            _params: Vec<u8>,
+            _rid: RoomId,
+            _seed: [u8; 32],
+        ) -> Option<(Self, Tasks<Self>)> {
+            Some((Game, vec![]))
+        }
+
+        async fn prove(&mut self) -> Result<(Vec<u8>, Vec<u8>)> {
+            Ok((vec![], vec![]))
+        }
+    }
+
+    fn engine(config: Config) -> (Engine<Game>, GameId) {
+        let game = Address::repeat_byte(1);
+        let mut engine = Engine::init(config);
+        engine.games.insert(game, vec![]);
+        (engine, game)
+    }
+
+    fn create(engine: &mut Engine<Game>, id: RoomId, game: GameId) {
+        let player = Player::new(Address::zero(), PeerId::default(), [0u8; 32]);
+        engine.create_pending(id, game, false, player, [0u8; 32], [0u8; 32]);
+    }
+
+    #[test]
+    fn pending_expired_by_age() {
+        let (mut engine, game) = engine(Config {
+            pending_ttl: 60,
+            ..Default::default()
+        });
+        create(&mut engine, 1, game);
+        create(&mut engine, 2, game);
+        assert!(engine.expired_pendings().is_empty());
+
+        engine.pending.get_mut(&1).unwrap().created -= Duration::from_secs(61);
+        assert_eq!(engine.expired_pendings(), vec![1]);
+
+        // the accepted room is waiting the chain, not expired
+        engine.transit(1, RoomStatus::Running);
+        assert!(engine.expired_pendings().is_empty());
+    }
+
+    #[test]
+    fn pending_expired_by_blocks() {
+        let (mut engine, game) = engine(Config {
+            pending_blocks: 10,
+            ..Default::default()
+        });
+        let local = local_room_id(3).unwrap();
+        create(&mut engine, 1, game);
+        create(&mut engine, local, game);
+        engine.update_height(0, 100);
+        create(&mut engine, 2, game);
+        assert_eq!(engine.pending[&1].height, Some(100));
+        assert_eq!(engine.pending[&2].height, Some(100));
+        assert_eq!(engine.pending[&local].height, None);
+
+        engine.update_height(0, 110);
+        assert!(engine.expired_pendings().is_empty());
+
+        engine.update_height(0, 111);
+        let mut expired = engine.expired_pendings();
+        expired.sort();
+        assert_eq!(expired, vec![1, 2]);
+    }
+
+    #[test]
+    fn running_room_idle() {
+        let (mut engine, game) = engine(Config {
+            room_idle: 60,
+            ..Default::default()
+        });
+        let id = local_room_id(1).unwrap();
+        create(&mut engine, id, game);
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let sender = engine.task_sender.clone();
+        let sequencer = (PeerId::default(), String::new());
+        runtime.block_on(engine.start_room(id, sequencer, vec![], true, sender));
+        assert!(engine.has_room(&id));
+        assert!(engine.idle_rooms().is_empty());
+
+        engine.rooms.get_mut(&id).unwrap().active -= Duration::from_secs(61);
+        assert_eq!(engine.idle_rooms(), vec![id]);
+
+        // the activity of players after limits check
+        engine.check_limit(id, PeerId::default(), 0).unwrap();
+        assert!(engine.idle_rooms().is_empty());
+    }
+}
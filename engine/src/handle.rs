@@ -0,0 +1,152 @@
+use tokio::sync::{broadcast, mpsc::UnboundedSender, oneshot};
+use z4_types::{local_room_id, Error, GameId, Param, PeerId, Player, Result, RoomId};
+
+/// The events of running engine
+#[derive(Clone, Debug)]
+pub enum EngineEvent {
+    /// pending room created, room_id, game_id
+    PendingCreated(RoomId, GameId),
+    /// new player joined the pending room, room_id, player
+    PendingJoined(RoomId, Player),
+    /// room accepted on chain, room_id, sequencer
+    RoomAccepted(RoomId, PeerId),
+    /// room started in this engine, room_id
+    RoomStarted(RoomId),
+    /// new player joined the running room, room_id, player
+    PlayerJoined(RoomId, Player),
+    /// room is locked and players cannot join, room_id
+    RoomLocked(RoomId),
+    /// player left the room, room_id, peer
+    PlayerLeft(RoomId, PeerId),
+    /// player forfeit the game, room_id, peer
+    PlayerForfeit(RoomId, PeerId),
+    /// the seat of player replaced, room_id, old peer, new player
+    PlayerReplaced(RoomId, PeerId, Player),
+    /// player/viewer online in the room, room_id, peer
+    PlayerOnline(RoomId, PeerId),
+    /// player/viewer offline in the room, room_id, peer
+    PlayerOffline(RoomId, PeerId),
+    /// room is over and removed from engine, room_id
+    RoomOver(RoomId),
+    /// proof of the room produced, room_id
+    ProofProduced(RoomId),
+    /// settlement transaction submitted, room_id
+    SettlementSubmitted(RoomId),
+    /// settlement transaction confirmed, room_id
+    SettlementConfirmed(RoomId),
+    /// settlement transaction failed, room_id, error
+    SettlementFailed(RoomId, String),
+}
+
+/// The room info when query
+#[derive(Clone, Debug)]
+pub struct RoomInfo {
+    /// the room id
+    pub id: RoomId,
+    /// the game id
+    pub game: GameId,
+    /// the room players
+    pub players: Vec<PeerId>,
+    /// seconds since the room started
+    pub age: u64,
+}
+
+/// The command send to running engine
+pub enum EngineCommand<P: Param> {
+    /// create and start a room in this engine,
+    /// local room_id, game_id, viewable, players, params, response
+    CreateRoom(
+        RoomId,
+        GameId,
+        bool,
+        Vec<Player>,
+        Vec<u8>,
+        oneshot::Sender<Result<()>>,
+    ),
+    /// close the room without prove,
+    /// room_id
+    CloseRoom(RoomId),
+    /// inject message into the room as from the player,
+    /// room_id, player peer, message
+    Inject(RoomId, PeerId, P),
+    /// query the room info,
+    /// room_id, response
+    QueryRoom(RoomId, oneshot::Sender<Option<RoomInfo>>),
+    /// stop accepting rooms, drain running rooms and exit
+    Shutdown,
+}
+
+/// The handle to control the running engine
+pub struct EngineHandle<P: Param> {
+    sender: UnboundedSender<EngineCommand<P>>,
+    events: broadcast::Sender<EngineEvent>,
+}
+
+impl<P: Param> Clone for EngineHandle<P> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl<P: Param> EngineHandle<P> {
+    /// Create handle with command channel
+    pub(crate) fn new(
+        sender: UnboundedSender<EngineCommand<P>>,
+        events: broadcast::Sender<EngineEvent>,
+    ) -> Self {
+        Self { sender, events }
+    }
+
+    /// Create and start a room in this engine, without chain.
+    /// The id is namespaced as local room, so not conflict with chain rooms,
+    /// return the namespaced room id which used in other methods and by clients,
+    /// error if the game is not supported or the room cannot be started
+    pub async fn create_room(
+        &self,
+        id: u64,
+        game: GameId,
+        viewable: bool,
+        players: Vec<Player>,
+        params: Vec<u8>,
+    ) -> Result<RoomId> {
+        let id = local_room_id(id).ok_or(Error::Params)?;
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(EngineCommand::CreateRoom(
+                id, game, viewable, players, params, tx,
+            ))
+            .map_err(|_| Error::NoRoom)?;
+        rx.await.map_err(|_| Error::NoRoom)??;
+        Ok(id)
+    }
+
+    /// Close the room without prove
+    pub fn close_room(&self, id: RoomId) {
+        let _ = self.sender.send(EngineCommand::CloseRoom(id));
+    }
+
+    /// Inject message into the room as from the player
+    pub fn inject(&self, id: RoomId, peer: PeerId, param: P) {
+        let _ = self.sender.send(EngineCommand::Inject(id, peer, param));
+    }
+
+    /// Query the room info, none if the room is not running
+    pub async fn room(&self, id: RoomId) -> Option<RoomInfo> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(EngineCommand::QueryRoom(id, tx)).ok()?;
+        rx.await.ok().flatten()
+    }
+
+    /// Subscribe the engine events
+    pub fn subscribe(&self) -> broadcast::Receiver<EngineEvent> {
+        self.events.subscribe()
+    }
+
+    /// Graceful shutdown the engine, running rooms will be settled before exit
+    pub fn shutdown(&self) {
+        let _ = self.sender.send(EngineCommand::Shutdown);
+    }
+}
@@ -1,27 +1,63 @@
 #[macro_use]
 extern crate tracing;
 
+mod admin;
+mod bot;
+mod builder;
 mod config;
 mod contracts;
 mod engine;
+mod handle;
+mod limit;
+mod metrics;
+mod observer;
 mod p2p;
+mod policy;
 mod pool;
+mod registry;
 mod room;
 mod rpc;
 mod scan;
+mod sequencer;
+mod status;
+mod storage;
 
 /// Module for ws/http/p2p request with channel.
 #[cfg(feature = "request")]
 pub mod request;
 
 /// Z4 main config.
-pub use config::Config;
+pub use config::{ChainParams, Config};
 
 /// Z4 main contracts.
 pub use contracts::{RoomMarket, SimpleGame, Token};
 
 /// Z4 main engine.
-pub use engine::Engine;
+pub use engine::{Engine, TransportChannel};
+
+/// Z4 engine builder for embedding.
+pub use builder::EngineBuilder;
+
+/// Z4 engine control handle.
+pub use handle::{EngineEvent, EngineHandle, RoomInfo};
+
+/// Z4 engine lifecycle observer.
+pub use observer::EngineObserver;
+
+/// Z4 engine policies.
+pub use policy::{AcceptAll, AcceptPolicy};
+
+/// Z4 sequencers registry synced from chain.
+pub use sequencer::{Sequencer, Sequencers};
+
+/// Z4 room lifecycle status.
+pub use status::{RoomLifecycle, RoomStatus};
+
+/// Z4 engine storage.
+pub use storage::{FileStorage, MemoryStorage, Storage};
+
+/// Z4 games registry, host multiple games in one engine.
+pub use registry::{AcceptFactory, CreateFactory, FactoryFuture, GameRegistry, Games};
 
 /// Create z4 scan(sync from chain) channel.
 pub use scan::chain_channel;
@@ -31,13 +67,14 @@ pub use serde_json::{json, Value};
 
 /// Export useful tdn core struct and functions.
 pub use tdn::{
-    prelude::{GroupId, Peer, PeerId, PeerKey, SendType},
+    prelude::{GroupId, Peer, PeerId, PeerKey, ReceiveMessage, RecvType, SendMessage, SendType},
     types::rpc::rpc_response,
 };
 
 /// Export useful types
 pub use z4_types::*;
 
+use ethers::prelude::U256;
 use serde::{Deserialize, Serialize};
 
 /// P2P network message type
@@ -50,21 +87,12 @@ struct P2pMessage<'a> {
 /// The message type synced from chain
 pub enum ChainMessage {
     /// create a room on the chain,
-    /// room_id, game_id, viewable, player account, player peer id, player pubkey,
+    /// room_id, game_id, viewable, player with pubkey,
     /// salt by player, current block prevrandao
-    CreateRoom(
-        RoomId,
-        GameId,
-        bool,
-        Address,
-        PeerId,
-        [u8; 32],
-        [u8; 32],
-        [u8; 32],
-    ),
+    CreateRoom(RoomId, GameId, bool, Player, [u8; 32], [u8; 32]),
     /// join a room on the chain,
-    /// room_id, player account, player peer id, player pubkey
-    JoinRoom(RoomId, Address, PeerId, [u8; 32]),
+    /// room_id, player with pubkey
+    JoinRoom(RoomId, Player),
     /// start a room on the chain,
     /// room_id, game address
     StartRoom(RoomId, Address),
@@ -77,8 +105,28 @@ pub enum ChainMessage {
     /// game over on the chain,
     /// room_id
     ChainOverRoom(RoomId),
-    /// need reprove in local
-    Reprove,
+    /// sequencer staked on the chain,
+    /// namespace of chain market, sequencer account, http url, websocket url,
+    /// staking after stake
+    StakeSequencer(usize, PeerId, String, String, U256),
+    /// sequencer unstaked on the chain,
+    /// namespace of chain market, sequencer account, staking after unstake
+    UnstakeSequencer(usize, PeerId, U256),
+    /// room claimed and deleted on the chain,
+    /// room_id
+    ClaimRoom(RoomId),
+    /// settlement failed, need reprove in local,
+    /// room_id
+    Reprove(RoomId),
+    /// the created room removed by chain reorg,
+    /// room_id
+    RevertCreateRoom(RoomId),
+    /// the joined player removed by chain reorg,
+    /// room_id, player peer id
+    RevertJoinRoom(RoomId, PeerId),
+    /// the block height of chain which logs scanned by quorum,
+    /// namespace of chain market, block number
+    Height(usize, u64),
 }
 
 /// The message type when send to pool
@@ -91,4 +139,6 @@ pub enum PoolMessage {
     OverRoom(RoomId, Vec<u8>, Vec<u8>),
     /// the transaction had been submmited
     Submitted(RoomId),
+    /// notify when all previous transactions had been mined
+    Flush(tokio::sync::oneshot::Sender<()>),
 }
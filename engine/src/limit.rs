@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::time::Instant;
+use z4_types::{Error, PeerId, Result, RoomId};
+
+use crate::config::Config;
+
+/// Token bucket for limit the messages rate
+pub struct TokenBucket {
+    /// max tokens in the bucket
+    capacity: f64,
+    /// current tokens in the bucket
+    tokens: f64,
+    /// tokens refilled per second
+    rate: f64,
+    /// last refill time
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// Create a full bucket, burst is at least the rate
+    pub fn new(rate: u32, burst: u32) -> Self {
+        let capacity = burst.max(rate) as f64;
+        Self {
+            capacity,
+            tokens: capacity,
+            rate: rate as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// Take a token from the bucket, return false if empty
+    pub fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Limit the messages size & rate of peers and rooms
+pub struct Limiter {
+    max_payload: usize,
+    peer_rate: u32,
+    peer_burst: u32,
+    room_rate: u32,
+    room_burst: u32,
+    max_violations: u32,
+    /// peers buckets and breaches in the room
+    peers: HashMap<(RoomId, PeerId), (TokenBucket, u32)>,
+    /// rooms buckets
+    rooms: HashMap<RoomId, TokenBucket>,
+}
+
+impl Limiter {
+    /// Create limiter from config
+    pub fn new(config: &Config) -> Self {
+        Self {
+            max_payload: config.max_payload,
+            peer_rate: config.peer_rate,
+            peer_burst: config.peer_burst,
+            room_rate: config.room_rate,
+            room_burst: config.room_burst,
+            max_violations: config.max_violations,
+            peers: HashMap::new(),
+            rooms: HashMap::new(),
+        }
+    }
+
+    /// Check the message from peer in the room, before deserialize it
+    pub fn check(&mut self, id: RoomId, peer: PeerId, size: usize) -> Result<()> {
+        if self.is_offender(id, &peer) {
+            return Err(Error::RateLimit);
+        }
+
+        let (peer_rate, peer_burst) = (self.peer_rate, self.peer_burst);
+        let (bucket, violations) = self
+            .peers
+            .entry((id, peer))
+            .or_insert_with(|| (TokenBucket::new(peer_rate, peer_burst), 0));
+
+        let res = if self.max_payload > 0 && size > self.max_payload {
+            Err(Error::PayloadTooLarge)
+        } else if (self.peer_rate > 0 && !bucket.take())
+            || (self.room_rate > 0
+                && !self
+                    .rooms
+                    .entry(id)
+                    .or_insert_with(|| TokenBucket::new(self.room_rate, self.room_burst))
+                    .take())
+        {
+            // the room bucket is not taken when the peer is limited
+            Err(Error::RateLimit)
+        } else {
+            Ok(())
+        };
+
+        if res.is_err() {
+            *violations += 1;
+        }
+        res
+    }
+
+    /// Check if the peer breached the limits too many times in the room
+    pub fn is_offender(&self, id: RoomId, peer: &PeerId) -> bool {
+        if self.max_violations == 0 {
+            return false;
+        }
+
+        self.peers
+            .get(&(id, *peer))
+            .map(|(_, v)| *v >= self.max_violations)
+            .unwrap_or(false)
+    }
+
+    /// Clear the room buckets when room is over
+    pub fn remove_room(&mut self, id: RoomId) {
+        self.rooms.remove(&id);
+        self.peers.retain(|(rid, _), _| *rid != id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(peer_rate: u32, room_rate: u32, max_violations: u32) -> Limiter {
+        let config = Config {
+            max_payload: 16,
+            peer_rate,
+            peer_burst: peer_rate,
+            room_rate,
+            room_burst: room_rate,
+            max_violations,
+            ..Default::default()
+        };
+        Limiter::new(&config)
+    }
+
+    #[test]
+    fn bucket_takes_burst_then_empty() {
+        let mut bucket = TokenBucket::new(1, 3);
+        assert!(bucket.take());
+        assert!(bucket.take());
+        assert!(bucket.take());
+        assert!(!bucket.take());
+    }
+
+    #[test]
+    fn bucket_refills_by_rate() {
+        let mut bucket = TokenBucket::new(1000, 1);
+        assert!(bucket.take());
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert!(bucket.take());
+    }
+
+    #[test]
+    fn limit_payload_and_peer_rate() {
+        let mut limiter = limiter(2, 0, 0);
+        let peer = PeerId::default();
+        assert!(matches!(
+            limiter.check(1, peer, 17),
+            Err(Error::PayloadTooLarge)
+        ));
+        assert!(limiter.check(1, peer, 16).is_ok());
+        assert!(limiter.check(1, peer, 16).is_ok());
+        assert!(matches!(limiter.check(1, peer, 16), Err(Error::RateLimit)));
+        // other rooms have their own buckets
+        assert!(limiter.check(2, peer, 16).is_ok());
+    }
+
+    #[test]
+    fn limit_room_rate() {
+        let mut limiter = limiter(0, 1, 0);
+        assert!(limiter.check(1, PeerId([1u8; 20]), 0).is_ok());
+        assert!(matches!(
+            limiter.check(1, PeerId([2u8; 20]), 0),
+            Err(Error::RateLimit)
+        ));
+    }
+
+    #[test]
+    fn offender_after_violations() {
+        let mut limiter = limiter(1, 0, 2);
+        let peer = PeerId::default();
+        assert!(limiter.check(1, peer, 0).is_ok());
+        assert!(limiter.check(1, peer, 0).is_err());
+        assert!(!limiter.is_offender(1, &peer));
+        assert!(limiter.check(1, peer, 0).is_err());
+        assert!(limiter.is_offender(1, &peer));
+
+        limiter.remove_room(1);
+        assert!(!limiter.is_offender(1, &peer));
+    }
+}
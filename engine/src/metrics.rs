@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use z4_types::Result;
+
+/// Histogram buckets in seconds
+const BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0,
+];
+
+/// The transport of messages
+#[derive(Clone, Copy)]
+pub enum Transport {
+    P2p = 0,
+    Ws = 1,
+    Http = 2,
+}
+
+const TRANSPORTS: [&str; 3] = ["p2p", "ws", "http"];
+
+/// Simple histogram with fixed buckets
+#[derive(Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    /// sum of all observed values in microseconds
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    /// Observe a duration
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        for (i, bound) in BUCKETS.iter().enumerate() {
+            if secs <= *bound {
+                self.buckets[i].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.sum
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, name: &str, help: &str, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (i, bound) in BUCKETS.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{le=\"{}\"}} {}",
+                name,
+                bound,
+                self.buckets[i].load(Ordering::Relaxed)
+            );
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(
+            out,
+            "{}_sum {}",
+            name,
+            self.sum.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        );
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+/// Metrics registry of engine, scan and pool
+#[derive(Default)]
+pub struct Metrics {
+    /// Rooms which waiting to start
+    pub rooms_pending: AtomicU64,
+    /// Rooms which is running
+    pub rooms_running: AtomicU64,
+    /// Rooms which is proving
+    pub rooms_proving: AtomicU64,
+    /// Messages received by transport
+    messages_in: [AtomicU64; 3],
+    /// Messages sent by transport
+    messages_out: [AtomicU64; 3],
+    /// Latency of handler handle messages
+    pub handler_latency: Histogram,
+    /// Duration of generate proof
+    pub proof_duration: Histogram,
+    /// Blocks of scan behind the head, by chain and provider
+    scan_lag: Mutex<BTreeMap<(usize, usize), u64>>,
+    /// Timeouts when scan from providers
+    pub scan_timeouts: AtomicU64,
+    /// Reorgs detected when scan from providers
+    pub scan_reorgs: AtomicU64,
+    /// Logs which missed by provider but reported by others, by chain and provider
+    scan_disagreements: Mutex<BTreeMap<(usize, usize), u64>>,
+    /// Transactions which confirmed
+    pub pool_success: AtomicU64,
+    /// Transactions which failed
+    pub pool_failure: AtomicU64,
+    /// Gas spent by confirmed transactions
+    pub pool_gas: AtomicU64,
+}
+
+impl Metrics {
+    /// Count a received message
+    pub fn message_in(&self, transport: Transport) {
+        self.messages_in[transport as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a sent message
+    pub fn message_out(&self, transport: Transport) {
+        self.messages_out[transport as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Update the scan lag of provider
+    pub fn scan_lag(&self, chain: usize, provider: usize, lag: u64) {
+        if let Ok(mut lock) = self.scan_lag.lock() {
+            lock.insert((chain, provider), lag);
+        }
+    }
+
+    /// Count the logs which missed by provider
+    pub fn scan_disagree(&self, chain: usize, provider: usize, missing: u64) {
+        if let Ok(mut lock) = self.scan_disagreements.lock() {
+            *lock.entry((chain, provider)).or_default() += missing;
+        }
+    }
+
+    /// Render all metrics with prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        gauge(
+            &mut out,
+            "z4_rooms_pending",
+            "Rooms which waiting to start",
+            &self.rooms_pending,
+        );
+        gauge(
+            &mut out,
+            "z4_rooms_running",
+            "Rooms which is running",
+            &self.rooms_running,
+        );
+        gauge(
+            &mut out,
+            "z4_rooms_proving",
+            "Rooms which is proving",
+            &self.rooms_proving,
+        );
+
+        let _ = writeln!(out, "# HELP z4_messages_in_total Messages received");
+        let _ = writeln!(out, "# TYPE z4_messages_in_total counter");
+        for (i, t) in TRANSPORTS.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "z4_messages_in_total{{transport=\"{}\"}} {}",
+                t,
+                self.messages_in[i].load(Ordering::Relaxed)
+            );
+        }
+        let _ = writeln!(out, "# HELP z4_messages_out_total Messages sent");
+        let _ = writeln!(out, "# TYPE z4_messages_out_total counter");
+        for (i, t) in TRANSPORTS.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "z4_messages_out_total{{transport=\"{}\"}} {}",
+                t,
+                self.messages_out[i].load(Ordering::Relaxed)
+            );
+        }
+
+        self.handler_latency.render(
+            "z4_handler_latency_seconds",
+            "Latency of handler handle messages",
+            &mut out,
+        );
+        self.proof_duration.render(
+            "z4_proof_duration_seconds",
+            "Duration of generate proof",
+            &mut out,
+        );
+
+        let _ = writeln!(out, "# HELP z4_scan_lag_blocks Blocks of scan behind the head");
+        let _ = writeln!(out, "# TYPE z4_scan_lag_blocks gauge");
+        if let Ok(lock) = self.scan_lag.lock() {
+            for ((chain, provider), lag) in lock.iter() {
+                let _ = writeln!(
+                    out,
+                    "z4_scan_lag_blocks{{chain=\"{}\",provider=\"{}\"}} {}",
+                    chain, provider, lag
+                );
+            }
+        }
+        counter(
+            &mut out,
+            "z4_scan_timeouts_total",
+            "Timeouts when scan from providers",
+            &self.scan_timeouts,
+        );
+        counter(
+            &mut out,
+            "z4_scan_reorgs_total",
+            "Reorgs detected when scan from providers",
+            &self.scan_reorgs,
+        );
+        let _ = writeln!(
+            out,
+            "# HELP z4_scan_disagreements_total Logs missed by provider but reported by others"
+        );
+        let _ = writeln!(out, "# TYPE z4_scan_disagreements_total counter");
+        if let Ok(lock) = self.scan_disagreements.lock() {
+            for ((chain, provider), missing) in lock.iter() {
+                let _ = writeln!(
+                    out,
+                    "z4_scan_disagreements_total{{chain=\"{}\",provider=\"{}\"}} {}",
+                    chain, provider, missing
+                );
+            }
+        }
+        counter(
+            &mut out,
+            "z4_pool_success_total",
+            "Transactions which confirmed",
+            &self.pool_success,
+        );
+        counter(
+            &mut out,
+            "z4_pool_failure_total",
+            "Transactions which failed",
+            &self.pool_failure,
+        );
+        counter(
+            &mut out,
+            "z4_pool_gas_total",
+            "Gas spent by confirmed transactions",
+            &self.pool_gas,
+        );
+
+        out
+    }
+}
+
+#[inline]
+fn gauge(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+}
+
+#[inline]
+fn counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+}
+
+/// Listen metrics http service, serve the metrics on /metrics
+pub async fn listen(port: u16, metrics: Arc<Metrics>) -> Result<()> {
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+
+    loop {
+        let (mut stream, _) = match listener.accept().await {
+            Ok(s) => s,
+            Err(err) => {
+                error!("Metrics: {}", err);
+                continue;
+            }
+        };
+
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let n = stream.read(&mut buf).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let response = if request.starts_with("GET /metrics") {
+                let body = metrics.render();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_owned()
+            };
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use z4_types::{GameId, PeerId, Player, RoomId};
+
+use crate::handle::EngineEvent;
+
+/// Observer of engine lifecycle, all callbacks are default empty,
+/// and running in its own task, so it will not block the engine
+#[async_trait::async_trait]
+pub trait EngineObserver: Send + Sync {
+    /// Pending room created from chain
+    async fn pending_created(&self, _room: RoomId, _game: GameId) {}
+
+    /// New player joined the pending room
+    async fn pending_joined(&self, _room: RoomId, _player: Player) {}
+
+    /// Room accepted on chain by the sequencer
+    async fn room_accepted(&self, _room: RoomId, _sequencer: PeerId) {}
+
+    /// Room started in this engine
+    async fn room_started(&self, _room: RoomId) {}
+
+    /// New player joined the running room when not locked
+    async fn player_joined(&self, _room: RoomId, _player: Player) {}
+
+    /// Room is locked and players cannot join
+    async fn room_locked(&self, _room: RoomId) {}
+
+    /// Player left the room
+    async fn player_left(&self, _room: RoomId, _peer: PeerId) {}
+
+    /// Player forfeit the game
+    async fn player_forfeit(&self, _room: RoomId, _peer: PeerId) {}
+
+    /// The seat of player replaced by new player
+    async fn player_replaced(&self, _room: RoomId, _old: PeerId, _player: Player) {}
+
+    /// Player/viewer online in the room
+    async fn player_online(&self, _room: RoomId, _peer: PeerId) {}
+
+    /// Player/viewer offline in the room
+    async fn player_offline(&self, _room: RoomId, _peer: PeerId) {}
+
+    /// Room is over and removed from engine
+    async fn room_over(&self, _room: RoomId) {}
+
+    /// Proof of the room produced
+    async fn proof_produced(&self, _room: RoomId) {}
+
+    /// Settlement transaction submitted to chain
+    async fn settlement_submitted(&self, _room: RoomId) {}
+
+    /// Settlement transaction confirmed on chain
+    async fn settlement_confirmed(&self, _room: RoomId) {}
+
+    /// Settlement transaction failed, with error message
+    async fn settlement_failed(&self, _room: RoomId, _error: String) {}
+}
+
+/// Dispatch engine events to the observer until engine stopped
+pub(crate) async fn observe(observer: Arc<dyn EngineObserver>, mut events: Receiver<EngineEvent>) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                warn!("Observer: lagged, skipped {} events", n);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        match event {
+            EngineEvent::PendingCreated(rid, game) => observer.pending_created(rid, game).await,
+            EngineEvent::PendingJoined(rid, player) => observer.pending_joined(rid, player).await,
+            EngineEvent::RoomAccepted(rid, peer) => observer.room_accepted(rid, peer).await,
+            EngineEvent::RoomStarted(rid) => observer.room_started(rid).await,
+            EngineEvent::PlayerJoined(rid, player) => observer.player_joined(rid, player).await,
+            EngineEvent::RoomLocked(rid) => observer.room_locked(rid).await,
+            EngineEvent::PlayerLeft(rid, peer) => observer.player_left(rid, peer).await,
+            EngineEvent::PlayerForfeit(rid, peer) => observer.player_forfeit(rid, peer).await,
+            EngineEvent::PlayerReplaced(rid, old, player) => {
+                observer.player_replaced(rid, old, player).await
+            }
+            EngineEvent::PlayerOnline(rid, peer) => observer.player_online(rid, peer).await,
+            EngineEvent::PlayerOffline(rid, peer) => observer.player_offline(rid, peer).await,
+            EngineEvent::RoomOver(rid) => observer.room_over(rid).await,
+            EngineEvent::ProofProduced(rid) => observer.proof_produced(rid).await,
+            EngineEvent::SettlementSubmitted(rid) => observer.settlement_submitted(rid).await,
+            EngineEvent::SettlementConfirmed(rid) => observer.settlement_confirmed(rid).await,
+            EngineEvent::SettlementFailed(rid, err) => observer.settlement_failed(rid, err).await,
+        }
+    }
+}
@@ -1,6 +1,10 @@
-use tdn::prelude::{GroupId, RecvType, SendMessage, SendType};
+use std::time::Instant;
+use tdn::prelude::{GroupId, Peer, RecvType, SendMessage, SendType};
 use tokio::sync::mpsc::Sender;
-use z4_types::{HandleResult, Handler, Param, Result};
+use z4_types::{
+    peer_to_address, Error, HandleResult, Handler, MethodValues, Param, Player, Result,
+    SystemMessage, Z4_CHAT_HISTORY, Z4_CHAT_SEND, Z4_FORFEIT, Z4_LEAVE,
+};
 
 use crate::{engine::Engine, room::ConnectType};
 
@@ -12,9 +16,33 @@ pub async fn handle_p2p<H: Handler>(
     msg: RecvType,
 ) -> Result<Option<HandleResult<H::Param>>> {
     match msg {
-        RecvType::Connect(peer, _data) => {
-            let mut handler = engine.get_room(&gid).handler.lock().await;
-            let res = handler.online(peer.id).await?;
+        RecvType::Connect(peer, data) => {
+            let pid = peer.id;
+            // new player join with params when room is not started
+            let room = &engine.get_room(&gid).room;
+            if !data.is_empty() && !room.is_started() && !room.is_player(&peer.id) {
+                // no signer in p2p connect, only the games without keys accept this join
+                let player = Player::new(peer_to_address(peer.id), peer.id, [0u8; 32]);
+                let res = engine.join_room(gid, player, data).await;
+                let is_ok = res.is_ok() && engine.online(gid, peer.id, ConnectType::P2p).await;
+                let _ = send
+                    .send(SendMessage::Group(
+                        gid,
+                        SendType::Result(0, peer, is_ok, false, vec![]),
+                    ))
+                    .await;
+                return res.map(Some);
+            }
+
+            let hr = engine.get_room(&gid);
+            let is_player = hr.room.is_player(&peer.id);
+            let ctx = hr.context.read().await.clone();
+            let mut handler = hr.handler.lock().await;
+            let res = if is_player {
+                handler.online(&ctx, peer.id).await?
+            } else {
+                handler.viewer_online(&ctx, peer.id).await?
+            };
             drop(handler);
 
             if engine.online(gid, peer.id, ConnectType::P2p).await {
@@ -24,6 +52,9 @@ pub async fn handle_p2p<H: Handler>(
                         SendType::Result(0, peer, true, false, vec![]),
                     ))
                     .await;
+                if !is_player {
+                    engine.send_snapshot(gid, pid, send).await;
+                }
             } else {
                 if !engine.has_peer(&peer.id).await {
                     // close the connections
@@ -41,19 +72,80 @@ pub async fn handle_p2p<H: Handler>(
         RecvType::Leave(peer) => {
             engine.offline(peer.id).await;
 
-            let mut handler = engine.get_room(&gid).handler.lock().await;
-            let res = handler.offline(peer.id).await?;
+            let hr = engine.get_room(&gid);
+            let ctx = hr.context.read().await.clone();
+            let mut handler = hr.handler.lock().await;
+            let res = if hr.room.is_player(&peer.id) {
+                handler.offline(&ctx, peer.id).await?
+            } else {
+                handler.viewer_offline(&ctx, peer.id).await?
+            };
             drop(handler);
 
             Ok(Some(res))
         }
         RecvType::Event(peer_id, data) => {
-            let param = H::Param::from_bytes(data)?;
+            if let Err(err) = engine.check_limit(gid, peer_id, data.len()) {
+                let error = MethodValues::new("error", vec![format!("{:?}", err).into()]);
+                let _ = send
+                    .send(SendMessage::Group(
+                        gid,
+                        SendType::Event(0, peer_id, error.to_bytes()),
+                    ))
+                    .await;
+
+                if engine.is_offender(gid, &peer_id) {
+                    warn!("Disconnect offender {:?} in room {}", peer_id, gid);
+                    let _ = send
+                        .send(SendMessage::Group(
+                            gid,
+                            SendType::Result(0, Peer::peer(peer_id), false, false, vec![]),
+                        ))
+                        .await;
+
+                    return engine.kick_offline(gid, peer_id).await;
+                }
+                return Err(err);
+            }
+
+            // inner methods in reserved namespace, never passed to the game handler
+            if let Ok(inner) = serde_json::from_slice::<MethodValues>(&data) {
+                if SystemMessage::is_system(&inner.method) {
+                    return match inner.method.as_str() {
+                        Z4_LEAVE => engine.leave_room(gid, peer_id).await.map(Some),
+                        Z4_FORFEIT => engine.forfeit_room(gid, peer_id).await.map(Some),
+                        Z4_CHAT_SEND => {
+                            let text = inner.params.first().and_then(|v| v.as_str()).unwrap_or("");
+                            engine
+                                .chat_send(gid, peer_id, text.to_owned(), send)
+                                .await?;
+                            Ok(None)
+                        }
+                        Z4_CHAT_HISTORY => {
+                            let history = engine.chat_history(gid, peer_id)?.to_values();
+                            let _ = send
+                                .send(SendMessage::Group(
+                                    gid,
+                                    SendType::Event(0, peer_id, history.to_bytes()),
+                                ))
+                                .await;
+                            Ok(None)
+                        }
+                        _ => Err(Error::Params),
+                    };
+                }
+            }
 
             if engine.is_room_player(&gid, &peer_id).await {
-                let mut handler = engine.get_room(&gid).handler.lock().await;
-                let res = handler.handle(peer_id, param).await?;
+                let start = Instant::now();
+                let hr = engine.get_room(&gid);
+                let ctx = hr.context.read().await.clone();
+                let mut handler = hr.handler.lock().await;
+                let param = handler.decode(data)?;
+                let res = handler.handle(&ctx, peer_id, param).await;
                 drop(handler);
+                engine.metrics.handler_latency.observe(start.elapsed());
+                let res = res?;
 
                 Ok(Some(res))
             } else {
@@ -0,0 +1,31 @@
+use z4_types::{GameId, Player, RoomId};
+
+use crate::sequencer::Sequencers;
+
+/// Policy to decide whether to accept the room which is started on chain
+#[async_trait::async_trait]
+pub trait AcceptPolicy: Send + Sync {
+    /// Return true if the engine should send the accept transaction
+    async fn accept(&self, room: RoomId, game: GameId, players: &[Player]) -> bool;
+
+    /// Same as accept, and can see the other active sequencers
+    async fn accept_with_sequencers(
+        &self,
+        room: RoomId,
+        game: GameId,
+        players: &[Player],
+        _sequencers: &Sequencers,
+    ) -> bool {
+        self.accept(room, game, players).await
+    }
+}
+
+/// Accept all rooms of supported games
+pub struct AcceptAll;
+
+#[async_trait::async_trait]
+impl AcceptPolicy for AcceptAll {
+    async fn accept(&self, _room: RoomId, _game: GameId, _players: &[Player]) -> bool {
+        true
+    }
+}
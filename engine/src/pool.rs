@@ -1,110 +1,473 @@
 use ethers::prelude::*;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
-use z4_types::{Result, RoomId};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{atomic::Ordering, Arc};
+use std::time::{Duration, Instant};
+use tokio::{
+    select,
+    sync::{
+        broadcast,
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot, Mutex,
+    },
+    time::interval,
+};
+use z4_types::{split_room_id, Result, RoomId};
 
 use crate::contracts::RoomMarket;
+use crate::handle::EngineEvent;
+use crate::metrics::Metrics;
+use crate::storage::Storage;
 use crate::{ChainMessage, PoolMessage};
 
 const GAS_PRICE: u64 = 20_000_000_000; // 20 GWEI
 const EXTRA_GAS: u64 = 10; // extra 10%
+const BUMP_GAS: u64 = 5; // bump 20% when replace
+const MAX_INFLIGHT: usize = 16; // max transactions waiting to be mined
+const MAX_BUMPS: u32 = 5; // max times of replacement
+const DEADLINE: u64 = 60; // seconds before replace the transaction
+const CHECK: u64 = 3; // seconds between two receipts checks
+
+type Client = SignerMiddleware<Arc<Provider<Http>>, LocalWallet>;
+
+/// The status of pool transactions
+#[derive(Default)]
+pub struct PoolStatus {
+    /// Accept transactions which confirmed
+    pub accepted: u64,
+    /// Over transactions which confirmed
+    pub settled: u64,
+    /// Transactions which failed
+    pub failed: u64,
+    /// Transactions which replaced with higher fee
+    pub replaced: u64,
+    /// Transactions which waiting to be mined
+    pub inflight: usize,
+    /// Rooms which waiting settlement
+    pub settling: Vec<RoomId>,
+}
+
+/// The transaction which sent and waiting to be mined
+#[derive(Serialize, Deserialize)]
+struct Inflight {
+    /// the room of transaction
+    room: RoomId,
+    /// over room transaction, or accept room transaction
+    over: bool,
+    /// the latest sent transaction, with nonce and gas price
+    tx: TypedTransaction,
+    /// hashes of all sent transactions with this nonce
+    hashes: Vec<H256>,
+    /// times of replacement
+    bumps: u32,
+    /// replaced by a self transfer after too many bumps, only to release the nonce
+    #[serde(default)]
+    cancel: bool,
+    /// the time when latest sent
+    #[serde(skip, default = "Instant::now")]
+    sent: Instant,
+}
+
+/// The nonce state which persisted for recovery after restart
+#[derive(Default, Serialize, Deserialize)]
+struct NonceState {
+    /// the next nonce
+    nonce: U256,
+    /// the transactions waiting to be mined, by nonce
+    inflight: Vec<Inflight>,
+}
 
 /// Create pool channel
 pub fn pool_channel() -> (UnboundedSender<PoolMessage>, UnboundedReceiver<PoolMessage>) {
     unbounded_channel()
 }
 
-/// Listen pool task
+/// The pool config of a chain market
+pub struct PoolConfig {
+    /// signer to send transactions
+    pub client: Arc<Client>,
+    /// room market address
+    pub market: Address,
+}
+
+/// Listen pool task, the rooms are namespaced ids of this chain market.
+/// Nonces are managed locally, so transactions are sent without waiting the previous,
+/// and replaced with higher fee when not mined before deadline
 pub async fn listen(
-    client: Arc<SignerMiddleware<Arc<Provider<Http>>, LocalWallet>>,
-    market_address: Address,
+    config: PoolConfig,
     sender: UnboundedSender<ChainMessage>,
     mut receiver: UnboundedReceiver<PoolMessage>,
+    status: Arc<Mutex<PoolStatus>>,
+    metrics: Arc<Metrics>,
+    events: broadcast::Sender<EngineEvent>,
+    storage: Arc<dyn Storage>,
 ) -> Result<()> {
-    let market = RoomMarket::new(market_address, client.clone());
-    let mut games: HashMap<RoomId, (Vec<u8>, Vec<u8>)> = HashMap::new();
-
-    while let Some(msg) = receiver.recv().await {
-        match msg {
-            PoolMessage::AcceptRoom(id, params) => {
-                let gas_price = market
-                    .client_ref()
-                    .get_gas_price()
-                    .await
-                    .unwrap_or(GAS_PRICE.into());
-                let extra_gas = gas_price + gas_price / U256::from(EXTRA_GAS);
+    let PoolConfig { client, market } = config;
+    let market = RoomMarket::new(market, client.clone());
+    let key = format!("pool-{}-{:?}", client.signer().chain_id(), client.address());
+    let mut pool = Pool {
+        client,
+        sender,
+        status,
+        metrics,
+        events,
+        storage,
+        key,
+        state: NonceState::default(),
+        queue: VecDeque::new(),
+        flushes: vec![],
+    };
+    pool.recover().await;
+
+    let mut ticker = interval(Duration::from_secs(CHECK));
+    loop {
+        select! {
+            msg = receiver.recv() => match msg {
+                Some(PoolMessage::AcceptRoom(id, params)) => {
+                    let call = market.accept_room(U256::from(split_room_id(id).1), params.into());
+                    pool.queue.push_back((id, false, call.tx));
+                }
+                Some(PoolMessage::OverRoom(id, result, proof)) => {
+                    pool.status.lock().await.settling.push(id);
 
-                match market
-                    .accept_room(U256::from(id), params.into())
-                    .gas_price(extra_gas)
-                    .send()
+                    let call = market.over_room_with_zk(
+                        U256::from(split_room_id(id).1),
+                        result.into(),
+                        proof.into(),
+                    );
+                    pool.queue.push_back((id, true, call.tx));
+                }
+                Some(PoolMessage::Submitted(id)) => {
+                    pool.status.lock().await.settling.retain(|rid| *rid != id);
+                }
+                Some(PoolMessage::Flush(notify)) => {
+                    pool.flushes.push(notify);
+                }
+                None => break,
+            },
+            _ = ticker.tick() => {
+                pool.check().await;
+            }
+        }
+
+        pool.send_queue().await;
+        pool.notify_flushes();
+    }
+
+    Ok(())
+}
+
+/// The pool of one signer
+struct Pool {
+    client: Arc<Client>,
+    sender: UnboundedSender<ChainMessage>,
+    status: Arc<Mutex<PoolStatus>>,
+    metrics: Arc<Metrics>,
+    events: broadcast::Sender<EngineEvent>,
+    storage: Arc<dyn Storage>,
+    /// the storage key of nonce state
+    key: String,
+    state: NonceState,
+    /// the transactions waiting to be sent, room, is over, transaction
+    queue: VecDeque<(RoomId, bool, TypedTransaction)>,
+    /// the notifiers waiting all transactions handled
+    flushes: Vec<oneshot::Sender<()>>,
+}
+
+impl Pool {
+    /// Recover the nonce state from storage, and sync the nonce with chain
+    async fn recover(&mut self) {
+        if let Some(bytes) = self.storage.get(&self.key) {
+            match serde_json::from_slice::<NonceState>(&bytes) {
+                Ok(state) => self.state = state,
+                Err(err) => error!("Pool: invalid nonce state: {}", err),
+            }
+        }
+        self.sync_nonce().await;
+        self.status.lock().await.inflight += self.state.inflight.len();
+        info!(
+            "Pool: next nonce {}, inflight {}",
+            self.state.nonce,
+            self.state.inflight.len()
+        );
+    }
+
+    /// Persist the nonce state
+    fn persist(&self) {
+        match serde_json::to_vec(&self.state) {
+            Ok(bytes) => {
+                if let Err(err) = self.storage.put(&self.key, bytes) {
+                    error!("Pool: {:?}", err);
+                }
+            }
+            Err(err) => error!("Pool: {}", err),
+        }
+    }
+
+    /// Make sure the next nonce is not less than the pending nonce on chain
+    async fn sync_nonce(&mut self) {
+        let pending = self
+            .client
+            .get_transaction_count(self.client.address(), Some(BlockNumber::Pending.into()))
+            .await;
+        match pending {
+            Ok(nonce) => {
+                if nonce > self.state.nonce {
+                    self.state.nonce = nonce;
+                }
+            }
+            Err(err) => error!("Pool: {}", err),
+        }
+    }
+
+    /// Send the queued transactions with next nonces
+    async fn send_queue(&mut self) {
+        while self.state.inflight.len() < MAX_INFLIGHT {
+            let (room, over, mut tx) = if let Some(item) = self.queue.pop_front() {
+                item
+            } else {
+                break;
+            };
+
+            let gas_price = self
+                .client
+                .get_gas_price()
+                .await
+                .unwrap_or(GAS_PRICE.into());
+            tx.set_gas_price(gas_price + gas_price / U256::from(EXTRA_GAS));
+            tx.set_nonce(self.state.nonce);
+
+            let sent = match self.client.fill_transaction(&mut tx, None).await {
+                Ok(()) => self
+                    .client
+                    .send_transaction(tx.clone(), None)
                     .await
-                {
-                    Ok(pending) => {
-                        if let Ok(receipt) = pending.await {
-                            info!(
-                                "Accepted, Gas used: {:?}",
-                                receipt
-                                    .expect("Failed to accept receipt")
-                                    .cumulative_gas_used
-                            );
-                        } else {
-                            error!("Failed to accept event");
-                        }
-                    }
-                    Err(err) => {
-                        if let Some(rcode) = err.decode_revert::<String>() {
-                            error!("{}", rcode);
-                        } else {
-                            error!("{}", err);
-                        }
+                    .map(|pending| pending.tx_hash()),
+                Err(err) => Err(err),
+            }
+            .map_err(revert_reason);
+            match sent {
+                Ok(hash) => {
+                    info!("Pool: sent room {} with nonce {}", room, self.state.nonce);
+                    if over {
+                        let _ = self.events.send(EngineEvent::SettlementSubmitted(room));
                     }
+                    self.state.nonce += U256::one();
+                    self.state.inflight.push(Inflight {
+                        room,
+                        over,
+                        tx,
+                        hashes: vec![hash],
+                        bumps: 0,
+                        cancel: false,
+                        sent: Instant::now(),
+                    });
+                    self.status.lock().await.inflight += 1;
+                    self.persist();
+                }
+                Err(error) => {
+                    error!("Pool: room {}: {}", room, error);
+                    self.failed(room, over, error).await;
+                    // the nonce maybe used by others
+                    self.sync_nonce().await;
                 }
             }
-            PoolMessage::OverRoom(id, result, proof) => {
-                games.insert(id, (result.clone(), proof.clone()));
+        }
+    }
 
-                let gas_price = market
-                    .client_ref()
-                    .get_gas_price()
-                    .await
-                    .unwrap_or(GAS_PRICE.into());
-                let extra_gas = gas_price + gas_price / U256::from(EXTRA_GAS);
+    /// Check the receipts of inflight transactions, replace them when stuck
+    async fn check(&mut self) {
+        let mut changed = false;
+        let mut i = 0;
+        while i < self.state.inflight.len() {
+            let mut receipt = None;
+            for hash in self.state.inflight[i].hashes.iter() {
+                if let Ok(Some(r)) = self.client.get_transaction_receipt(*hash).await {
+                    receipt = Some(r);
+                    break;
+                }
+            }
 
-                match market
-                    .over_room_with_zk(U256::from(id), result.into(), proof.into())
-                    .gas_price(extra_gas)
-                    .send()
-                    .await
-                {
-                    Ok(pending) => {
-                        if let Ok(receipt) = pending.await {
-                            info!(
-                                "Game over sent, Gas used: {:?}",
-                                receipt
-                                    .expect("Failed to claim receipt")
-                                    .cumulative_gas_used
-                            );
-                        } else {
-                            error!("Failed to sent event");
+            if let Some(receipt) = receipt {
+                let inflight = self.state.inflight.remove(i);
+                self.status.lock().await.inflight -= 1;
+                self.mined(inflight, receipt).await;
+                changed = true;
+                continue;
+            }
+
+            if self.state.inflight[i].sent.elapsed() > Duration::from_secs(DEADLINE)
+                && self.state.inflight[i].bumps >= MAX_BUMPS
+                && !self.state.inflight[i].cancel
+            {
+                let (room, over) = (self.state.inflight[i].room, self.state.inflight[i].over);
+                warn!("Pool: room {} stuck after {} bumps", room, MAX_BUMPS);
+                self.failed(room, over, "stuck".to_owned()).await;
+                self.cancel(i).await;
+                changed = true;
+            }
+
+            let inflight = &mut self.state.inflight[i];
+            if inflight.sent.elapsed() > Duration::from_secs(DEADLINE) {
+                if inflight.bumps >= MAX_BUMPS {
+                    warn!("Pool: cancel of room {} stuck", inflight.room);
+                    inflight.sent = Instant::now();
+                } else {
+                    let bumped = bump_price(inflight.tx.gas_price().unwrap_or(GAS_PRICE.into()));
+                    let mut tx = inflight.tx.clone();
+                    tx.set_gas_price(bumped);
+
+                    match self.client.send_transaction(tx.clone(), None).await {
+                        Ok(pending) => {
+                            info!("Pool: replaced room {} with gas {}", inflight.room, bumped);
+                            inflight.tx = tx;
+                            inflight.hashes.push(pending.tx_hash());
+                            inflight.bumps += 1;
+                            inflight.sent = Instant::now();
+                            self.status.lock().await.replaced += 1;
+                            changed = true;
                         }
-                    }
-                    Err(err) => {
-                        if let Some(rcode) = err.decode_revert::<String>() {
-                            error!("{}", rcode);
-                        } else {
-                            error!("{}", err);
+                        Err(err) => {
+                            // maybe mined just now, check it next time
+                            warn!("Pool: replace room {}: {}", inflight.room, err);
+                            inflight.sent = Instant::now();
                         }
-                        let _ = sender.send(ChainMessage::Reprove);
                     }
                 }
             }
-            PoolMessage::Submitted(id) => {
-                games.remove(&id);
+            i += 1;
+        }
+
+        if changed {
+            self.persist();
+        }
+    }
+
+    /// Replace the stuck transaction with a self transfer, so the later nonces can be mined
+    async fn cancel(&mut self, i: usize) {
+        let address = self.client.address();
+        let inflight = &mut self.state.inflight[i];
+        let gas_price = bump_price(inflight.tx.gas_price().unwrap_or(GAS_PRICE.into()));
+        let tx: TypedTransaction = TransactionRequest::new()
+            .from(address)
+            .to(address)
+            .value(0)
+            .gas(21_000)
+            .gas_price(gas_price)
+            .nonce(inflight.tx.nonce().copied().unwrap_or_default())
+            .into();
+
+        inflight.cancel = true;
+        inflight.bumps = 0;
+        inflight.sent = Instant::now();
+        match self.client.send_transaction(tx.clone(), None).await {
+            Ok(pending) => {
+                info!("Pool: cancel room {} with self transfer", inflight.room);
+                inflight.tx = tx;
+                inflight.hashes.push(pending.tx_hash());
+            }
+            Err(err) => {
+                // bump the cancel transaction next time
+                warn!("Pool: cancel room {}: {}", inflight.room, err);
+                inflight.tx = tx;
             }
         }
     }
 
-    Ok(())
+    /// The transaction mined, success or reverted
+    async fn mined(&mut self, inflight: Inflight, receipt: TransactionReceipt) {
+        let room = inflight.room;
+        let gas = receipt.gas_used.unwrap_or(receipt.cumulative_gas_used);
+        self.metrics
+            .pool_gas
+            .fetch_add(gas.as_u64(), Ordering::Relaxed);
+
+        if inflight.cancel {
+            // already failed when cancelled
+            info!("Pool: room {} cancelled, Gas used: {:?}", room, gas);
+            return;
+        }
+
+        if receipt.status == Some(U64::one()) {
+            info!("Pool: room {} mined, Gas used: {:?}", room, gas);
+            self.metrics.pool_success.fetch_add(1, Ordering::Relaxed);
+            if inflight.over {
+                self.status.lock().await.settled += 1;
+                let _ = self.events.send(EngineEvent::SettlementConfirmed(room));
+            } else {
+                self.status.lock().await.accepted += 1;
+            }
+        } else {
+            // replay the transaction on the state of previous block to get the revert reason,
+            // the transactions before it in the same block are not replayed, so it is best-effort
+            let block = receipt.block_number.map(|n| (n - 1).into());
+            let error = match self.client.call(&inflight.tx, block).await {
+                Ok(_) => "reverted".to_owned(),
+                Err(err) => format!("reverted, maybe: {}", revert_reason(err)),
+            };
+            error!("Pool: room {} reverted: {}", room, error);
+            self.failed(room, inflight.over, error).await;
+        }
+    }
+
+    /// The transaction failed
+    async fn failed(&mut self, room: RoomId, over: bool, error: String) {
+        self.status.lock().await.failed += 1;
+        self.metrics.pool_failure.fetch_add(1, Ordering::Relaxed);
+        if over {
+            let _ = self.events.send(EngineEvent::SettlementFailed(room, error));
+            let _ = self.sender.send(ChainMessage::Reprove(room));
+        }
+    }
+
+    /// Notify the flushes when all transactions handled
+    fn notify_flushes(&mut self) {
+        if self.queue.is_empty() && self.state.inflight.is_empty() {
+            for notify in self.flushes.drain(..) {
+                let _ = notify.send(());
+            }
+        }
+    }
+}
+
+/// The gas price to replace the transaction, at least one more than before
+fn bump_price(gas_price: U256) -> U256 {
+    gas_price + (gas_price / U256::from(BUMP_GAS)).max(U256::one())
+}
+
+/// Decode the revert reason from the middleware error
+fn revert_reason(err: <Client as Middleware>::Error) -> String {
+    let err = ContractError::<Client>::from_middleware_error(err);
+    if let Some(rcode) = err.decode_revert::<String>() {
+        rcode
+    } else {
+        err.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bump_price_by_percent() {
+        let gas_price = U256::from(GAS_PRICE);
+        assert_eq!(bump_price(gas_price), U256::from(24_000_000_000u64));
+        assert_eq!(bump_price(U256::from(100)), U256::from(120));
+    }
+
+    #[test]
+    fn bump_price_always_higher() {
+        assert_eq!(bump_price(U256::zero()), U256::one());
+        assert_eq!(bump_price(U256::from(4)), U256::from(5));
+
+        // every replacement is higher than all before
+        let mut gas_price = U256::from(GAS_PRICE);
+        for _ in 0..MAX_BUMPS {
+            let bumped = bump_price(gas_price);
+            assert!(bumped > gas_price);
+            gas_price = bumped;
+        }
+    }
 }
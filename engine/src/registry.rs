@@ -0,0 +1,620 @@
+use serde_json::Value;
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use z4_types::{
+    Bot, Bots, Error, GameId, HandleResult, Handler, Param, PeerId, Player, Result, RoomContext,
+    RoomId, Task, Tasks,
+};
+
+/// Boxed future of the factory
+pub type FactoryFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// Factory to build the accept params when submit to chain,
+/// players
+pub type AcceptFactory = Box<dyn Fn(Vec<Player>) -> FactoryFuture<Vec<u8>> + Send + Sync>;
+
+/// Factory to create the handler of new room,
+/// players, params, room id, seed
+pub type CreateFactory<H> = Box<
+    dyn Fn(Vec<Player>, Vec<u8>, RoomId, [u8; 32]) -> FactoryFuture<Option<(H, Tasks<H>)>>
+        + Send
+        + Sync,
+>;
+
+/// The registry of games, every game has its own handler factory
+pub struct GameRegistry<H: Handler> {
+    factories: HashMap<GameId, (AcceptFactory, CreateFactory<H>)>,
+    /// games registered with its own requirement of valid public keys
+    keys: HashMap<GameId, bool>,
+}
+
+impl<H: Handler> Default for GameRegistry<H> {
+    fn default() -> Self {
+        Self {
+            factories: HashMap::new(),
+            keys: HashMap::new(),
+        }
+    }
+}
+
+impl<H: Handler> GameRegistry<H> {
+    /// Register the game with factories
+    pub fn register(&mut self, game: GameId, accept: AcceptFactory, create: CreateFactory<H>) {
+        self.factories.insert(game, (accept, create));
+    }
+
+    /// Get all registered games
+    pub fn games(&self) -> Vec<GameId> {
+        self.factories.keys().copied().collect()
+    }
+
+    /// Check the game is registered
+    pub fn contains(&self, game: &GameId) -> bool {
+        self.factories.contains_key(game)
+    }
+
+    /// Check the players can be accepted or join, the keys are validated unless game opts out
+    pub fn validate(&self, game: &GameId, players: &[Player]) -> bool {
+        let required = self
+            .keys
+            .get(game)
+            .copied()
+            .unwrap_or_else(H::validate_keys);
+        !required || players.iter().all(|p| p.pk.is_some())
+    }
+
+    /// Build the accept params of the game, default is Handler::chain_accept
+    pub async fn accept(&self, game: &GameId, players: &[Player]) -> Vec<u8> {
+        if let Some((accept, _)) = self.factories.get(game) {
+            accept(players.to_vec()).await
+        } else {
+            H::chain_accept(players).await
+        }
+    }
+
+    /// Create the handler of the game, default is Handler::chain_create
+    pub async fn create(
+        &self,
+        game: &GameId,
+        players: &[Player],
+        params: Vec<u8>,
+        rid: RoomId,
+        seed: [u8; 32],
+    ) -> Option<(H, Tasks<H>)> {
+        if let Some((_, create)) = self.factories.get(game) {
+            create(players.to_vec(), params, rid, seed).await
+        } else {
+            H::chain_create(players, params, rid, seed).await
+        }
+    }
+}
+
+impl GameRegistry<Games> {
+    /// Register the game with its handler type, the game can use its own param type
+    pub fn register_game<G: Handler>(&mut self, game: GameId) {
+        self.keys.insert(game, G::validate_keys());
+        self.register(
+            game,
+            Box::new(|players: Vec<Player>| {
+                Box::pin(async move { G::chain_accept(&players).await }) as FactoryFuture<_>
+            }),
+            Box::new(
+                |players: Vec<Player>, params: Vec<u8>, rid: RoomId, seed: [u8; 32]| {
+                    Box::pin(async move {
+                        G::chain_create(&players, params, rid, seed)
+                            .await
+                            .map(|(handler, tasks)| (Games::new(handler), erase_tasks(tasks)))
+                    }) as FactoryFuture<_>
+                },
+            ),
+        );
+    }
+}
+
+/// Type-erased handler, the params of game are erased to json value,
+/// so different games with their own param types can be hosted in one engine
+#[async_trait::async_trait]
+trait ErasedHandler: Send {
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    fn debug_state(&self) -> Value;
+
+    fn lobby(&self) -> bool;
+
+    fn view(&self, param: &Value) -> Option<Value>;
+
+    fn snapshot(&self) -> Option<Value>;
+
+    fn decode(&self, bytes: Vec<u8>) -> Result<Value>;
+
+    fn encode(&self, param: &Value) -> Vec<u8>;
+
+    async fn pozk_join(&mut self, player: Player, params: Vec<u8>) -> Result<HandleResult<Value>>;
+
+    async fn viewer_online(
+        &mut self,
+        ctx: &RoomContext,
+        peer: PeerId,
+    ) -> Result<HandleResult<Value>>;
+
+    async fn viewer_offline(
+        &mut self,
+        ctx: &RoomContext,
+        peer: PeerId,
+    ) -> Result<HandleResult<Value>>;
+
+    async fn online(&mut self, ctx: &RoomContext, peer: PeerId) -> Result<HandleResult<Value>>;
+
+    async fn offline(&mut self, ctx: &RoomContext, peer: PeerId) -> Result<HandleResult<Value>>;
+
+    async fn handle(
+        &mut self,
+        ctx: &RoomContext,
+        peer: PeerId,
+        param: Value,
+    ) -> Result<HandleResult<Value>>;
+
+    async fn join(
+        &mut self,
+        ctx: &RoomContext,
+        player: Player,
+        params: Vec<u8>,
+    ) -> Result<HandleResult<Value>>;
+
+    fn take_bots(&mut self) -> Bots<Value>;
+
+    async fn fill_seats(&mut self, ctx: &RoomContext) -> Bots<Value>;
+
+    async fn leave(&mut self, ctx: &RoomContext, peer: PeerId) -> Result<HandleResult<Value>>;
+
+    async fn forfeit(&mut self, ctx: &RoomContext, peer: PeerId) -> Result<HandleResult<Value>>;
+
+    async fn on_idle(&mut self, ctx: &RoomContext) -> Result<HandleResult<Value>>;
+
+    async fn prove(&mut self) -> Result<(Vec<u8>, Vec<u8>)>;
+}
+
+#[async_trait::async_trait]
+impl<G: Handler> ErasedHandler for G {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn debug_state(&self) -> Value {
+        <G as Handler>::debug_state(self)
+    }
+
+    fn lobby(&self) -> bool {
+        <G as Handler>::lobby(self)
+    }
+
+    fn view(&self, param: &Value) -> Option<Value> {
+        let param = G::Param::from_value(param.clone()).ok()?;
+        <G as Handler>::view(self, &param).map(|p| p.to_value())
+    }
+
+    fn snapshot(&self) -> Option<Value> {
+        <G as Handler>::snapshot(self).map(|p| p.to_value())
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> Result<Value> {
+        <G as Handler>::decode(self, bytes).map(|p| p.to_value())
+    }
+
+    fn encode(&self, param: &Value) -> Vec<u8> {
+        match G::Param::from_value(param.clone()) {
+            Ok(param) => <G as Handler>::encode(self, &param),
+            Err(_) => param.to_bytes(),
+        }
+    }
+
+    async fn pozk_join(&mut self, player: Player, params: Vec<u8>) -> Result<HandleResult<Value>> {
+        <G as Handler>::pozk_join(self, player, params)
+            .await
+            .map(erase_result)
+    }
+
+    async fn viewer_online(
+        &mut self,
+        ctx: &RoomContext,
+        peer: PeerId,
+    ) -> Result<HandleResult<Value>> {
+        <G as Handler>::viewer_online(self, ctx, peer)
+            .await
+            .map(erase_result)
+    }
+
+    async fn viewer_offline(
+        &mut self,
+        ctx: &RoomContext,
+        peer: PeerId,
+    ) -> Result<HandleResult<Value>> {
+        <G as Handler>::viewer_offline(self, ctx, peer)
+            .await
+            .map(erase_result)
+    }
+
+    async fn online(&mut self, ctx: &RoomContext, peer: PeerId) -> Result<HandleResult<Value>> {
+        <G as Handler>::online(self, ctx, peer)
+            .await
+            .map(erase_result)
+    }
+
+    async fn offline(&mut self, ctx: &RoomContext, peer: PeerId) -> Result<HandleResult<Value>> {
+        <G as Handler>::offline(self, ctx, peer)
+            .await
+            .map(erase_result)
+    }
+
+    async fn handle(
+        &mut self,
+        ctx: &RoomContext,
+        peer: PeerId,
+        param: Value,
+    ) -> Result<HandleResult<Value>> {
+        let param = G::Param::from_value(param)?;
+        <G as Handler>::handle(self, ctx, peer, param)
+            .await
+            .map(erase_result)
+    }
+
+    async fn join(
+        &mut self,
+        ctx: &RoomContext,
+        player: Player,
+        params: Vec<u8>,
+    ) -> Result<HandleResult<Value>> {
+        <G as Handler>::join(self, ctx, player, params)
+            .await
+            .map(erase_result)
+    }
+
+    fn take_bots(&mut self) -> Bots<Value> {
+        erase_bots(<G as Handler>::take_bots(self))
+    }
+
+    async fn fill_seats(&mut self, ctx: &RoomContext) -> Bots<Value> {
+        erase_bots(<G as Handler>::fill_seats(self, ctx).await)
+    }
+
+    async fn leave(&mut self, ctx: &RoomContext, peer: PeerId) -> Result<HandleResult<Value>> {
+        <G as Handler>::leave(self, ctx, peer)
+            .await
+            .map(erase_result)
+    }
+
+    async fn forfeit(&mut self, ctx: &RoomContext, peer: PeerId) -> Result<HandleResult<Value>> {
+        <G as Handler>::forfeit(self, ctx, peer)
+            .await
+            .map(erase_result)
+    }
+
+    async fn on_idle(&mut self, ctx: &RoomContext) -> Result<HandleResult<Value>> {
+        <G as Handler>::on_idle(self, ctx).await.map(erase_result)
+    }
+
+    async fn prove(&mut self) -> Result<(Vec<u8>, Vec<u8>)> {
+        <G as Handler>::prove(self).await
+    }
+}
+
+/// The handler which dispatch to the registered game handler,
+/// the params are json values and converted to the param type of game
+pub struct Games {
+    inner: Box<dyn ErasedHandler>,
+}
+
+impl Games {
+    /// Wrap the game handler
+    pub fn new<G: Handler>(handler: G) -> Self {
+        Self {
+            inner: Box::new(handler),
+        }
+    }
+
+    /// Get the game handler if it is the type
+    pub fn downcast_mut<G: Handler>(&mut self) -> Option<&mut G> {
+        self.inner.as_any_mut().downcast_mut::<G>()
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler for Games {
+    type Param = Value;
+
+    async fn pozk_join(&mut self, player: Player, params: Vec<u8>) -> Result<HandleResult<Value>> {
+        self.inner.pozk_join(player, params).await
+    }
+
+    async fn viewer_online(
+        &mut self,
+        ctx: &RoomContext,
+        peer: PeerId,
+    ) -> Result<HandleResult<Value>> {
+        self.inner.viewer_online(ctx, peer).await
+    }
+
+    async fn viewer_offline(
+        &mut self,
+        ctx: &RoomContext,
+        peer: PeerId,
+    ) -> Result<HandleResult<Value>> {
+        self.inner.viewer_offline(ctx, peer).await
+    }
+
+    async fn online(&mut self, ctx: &RoomContext, peer: PeerId) -> Result<HandleResult<Value>> {
+        self.inner.online(ctx, peer).await
+    }
+
+    async fn offline(&mut self, ctx: &RoomContext, peer: PeerId) -> Result<HandleResult<Value>> {
+        self.inner.offline(ctx, peer).await
+    }
+
+    async fn handle(
+        &mut self,
+        ctx: &RoomContext,
+        peer: PeerId,
+        param: Value,
+    ) -> Result<HandleResult<Value>> {
+        self.inner.handle(ctx, peer, param).await
+    }
+
+    async fn join(
+        &mut self,
+        ctx: &RoomContext,
+        player: Player,
+        params: Vec<u8>,
+    ) -> Result<HandleResult<Value>> {
+        self.inner.join(ctx, player, params).await
+    }
+
+    fn take_bots(&mut self) -> Bots<Value> {
+        self.inner.take_bots()
+    }
+
+    async fn fill_seats(&mut self, ctx: &RoomContext) -> Bots<Value> {
+        self.inner.fill_seats(ctx).await
+    }
+
+    async fn leave(&mut self, ctx: &RoomContext, peer: PeerId) -> Result<HandleResult<Value>> {
+        self.inner.leave(ctx, peer).await
+    }
+
+    async fn forfeit(&mut self, ctx: &RoomContext, peer: PeerId) -> Result<HandleResult<Value>> {
+        self.inner.forfeit(ctx, peer).await
+    }
+
+    async fn on_idle(&mut self, ctx: &RoomContext) -> Result<HandleResult<Value>> {
+        self.inner.on_idle(ctx).await
+    }
+
+    async fn prove(&mut self) -> Result<(Vec<u8>, Vec<u8>)> {
+        self.inner.prove().await
+    }
+
+    fn debug_state(&self) -> Value {
+        self.inner.debug_state()
+    }
+
+    fn lobby(&self) -> bool {
+        self.inner.lobby()
+    }
+
+    fn view(&self, param: &Value) -> Option<Value> {
+        self.inner.view(param)
+    }
+
+    fn snapshot(&self) -> Option<Value> {
+        self.inner.snapshot()
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> Result<Value> {
+        self.inner.decode(bytes)
+    }
+
+    fn encode(&self, param: &Value) -> Vec<u8> {
+        self.inner.encode(param)
+    }
+}
+
+/// Convert the result of game handler to json values
+fn erase_result<P: Param>(result: HandleResult<P>) -> HandleResult<Value> {
+    let HandleResult {
+        all,
+        one,
+        over,
+        started,
+        replace,
+    } = result;
+
+    HandleResult {
+        all: all.iter().map(|p| p.to_value()).collect(),
+        one: one.iter().map(|(peer, p)| (*peer, p.to_value())).collect(),
+        over,
+        started,
+        replace,
+    }
+}
+
+/// Bot of game handler which play with json values
+struct ErasedBot<P: Param> {
+    bot: Box<dyn Bot<Param = P>>,
+}
+
+#[async_trait::async_trait]
+impl<P: Param + 'static> Bot for ErasedBot<P> {
+    type Param = Value;
+
+    fn timer(&self) -> u64 {
+        self.bot.timer()
+    }
+
+    async fn receive(&mut self, ctx: &RoomContext, param: Value) -> Vec<Value> {
+        match P::from_value(param) {
+            Ok(param) => self
+                .bot
+                .receive(ctx, param)
+                .await
+                .iter()
+                .map(|p| p.to_value())
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    async fn act(&mut self, ctx: &RoomContext) -> Vec<Value> {
+        self.bot
+            .act(ctx)
+            .await
+            .iter()
+            .map(|p| p.to_value())
+            .collect()
+    }
+}
+
+/// Convert game handler bots to bots with json values
+fn erase_bots<P: Param + 'static>(bots: Bots<P>) -> Bots<Value> {
+    bots.into_iter()
+        .map(|(player, bot)| {
+            (
+                player,
+                Box::new(ErasedBot { bot }) as Box<dyn Bot<Param = Value>>,
+            )
+        })
+        .collect()
+}
+
+/// Task of game handler which running with the type-erased handler
+struct ErasedTask<G: Handler> {
+    task: Box<dyn Task<H = G>>,
+}
+
+#[async_trait::async_trait]
+impl<G: Handler> Task for ErasedTask<G> {
+    type H = Games;
+
+    fn timer(&self) -> u64 {
+        self.task.timer()
+    }
+
+    async fn run(&mut self, state: &mut Games) -> Result<HandleResult<Value>> {
+        match state.downcast_mut::<G>() {
+            Some(handler) => self.task.run(handler).await.map(erase_result),
+            None => Err(Error::NoGame),
+        }
+    }
+
+    async fn run_with_context(
+        &mut self,
+        state: &mut Games,
+        ctx: &RoomContext,
+    ) -> Result<HandleResult<Value>> {
+        match state.downcast_mut::<G>() {
+            Some(handler) => self
+                .task
+                .run_with_context(handler, ctx)
+                .await
+                .map(erase_result),
+            None => Err(Error::NoGame),
+        }
+    }
+}
+
+/// Convert game handler tasks to type-erased handler tasks
+fn erase_tasks<G: Handler>(tasks: Tasks<G>) -> Tasks<Games> {
+    tasks
+        .into_iter()
+        .map(|task| Box::new(ErasedTask { task }) as Box<dyn Task<H = Games>>)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_serialize::{CanonicalSerialize, Compress};
+    use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
+    use z4_types::{generate_keypair, Address, PeerId};
+
+    struct Keyed;
+
+    #[async_trait::async_trait]
+    impl Handler for Keyed {
+        type Param = Value;
+
+        async fn prove(&mut self) -> Result<(Vec<u8>, Vec<u8>)> {
+            Ok((vec![], vec![]))
+        }
+    }
+
+    struct Unkeyed;
+
+    #[async_trait::async_trait]
+    impl Handler for Unkeyed {
+        type Param = Value;
+
+        fn validate_keys() -> bool {
+            false
+        }
+
+        async fn prove(&mut self) -> Result<(Vec<u8>, Vec<u8>)> {
+            Ok((vec![], vec![]))
+        }
+    }
+
+    struct Raw;
+
+    #[async_trait::async_trait]
+    impl Handler for Raw {
+        type Param = Vec<u8>;
+
+        async fn prove(&mut self) -> Result<(Vec<u8>, Vec<u8>)> {
+            Ok((vec![], vec![]))
+        }
+    }
+
+    fn player(signer: [u8; 32]) -> Player {
+        Player::new(Address::zero(), PeerId::default(), signer)
+    }
+
+    fn valid_player() -> Player {
+        let (_, pk) = generate_keypair(&mut ChaChaRng::seed_from_u64(7));
+        let mut signer = [0u8; 32];
+        pk.serialize_with_mode(&mut signer[..], Compress::Yes)
+            .unwrap();
+        player(signer)
+    }
+
+    #[test]
+    fn dummy_keys_rejected_by_default() {
+        let registry = GameRegistry::<Keyed>::default();
+        let game = Address::repeat_byte(1);
+
+        assert!(registry.validate(&game, &[valid_player()]));
+        assert!(!registry.validate(&game, &[player([0u8; 32]), valid_player()]));
+        assert!(valid_player().pk.is_some());
+        assert!(player([0u8; 32]).pk.is_none());
+    }
+
+    #[test]
+    fn dummy_keys_accepted_when_opt_out() {
+        let mut registry = GameRegistry::<Games>::default();
+        let keyed = Address::repeat_byte(1);
+        let unkeyed = Address::repeat_byte(2);
+        registry.register_game::<Keyed>(keyed);
+        registry.register_game::<Unkeyed>(unkeyed);
+
+        assert!(registry.validate(&keyed, &[valid_player()]));
+        assert!(!registry.validate(&keyed, &[valid_player(), player([0u8; 32])]));
+        assert!(registry.validate(&unkeyed, &[player([0u8; 32])]));
+    }
+
+    #[test]
+    fn game_param_bytes_not_json() {
+        let games = Games::new(Raw);
+        let value = Handler::decode(&games, vec![1, 2, 3]).unwrap();
+        assert_eq!(value, Value::String("010203".to_owned()));
+        assert_eq!(Handler::encode(&games, &value), vec![1, 2, 3]);
+    }
+}
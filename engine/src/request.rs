@@ -13,7 +13,13 @@ use tokio_tungstenite::{
     tungstenite::{client::IntoClientRequest, protocol::Message},
     MaybeTlsStream, WebSocketStream,
 };
-use z4_types::{json, merge_json, Param, Result, RoomId, Value};
+use z4_types::{json, merge_json, Param, Result, RoomId, SystemMessage, Value, Z4_PING, Z4_PONG};
+
+/// Seconds between two heartbeats of websocket channel, before the server idle timeout known
+const HEARTBEAT: u64 = 10;
+
+/// Heartbeats in the server idle timeout
+const HEARTBEAT_TIMES: u64 = 3;
 
 /// Channel message
 pub type ChannelMessage<P> = (RoomId, P);
@@ -69,6 +75,7 @@ pub async fn run_p2p_channel<P: 'static + Param>(
 enum WsResult<P: Param> {
     Out(ChannelMessage<P>),
     Stream(Message),
+    Ping,
 }
 
 #[inline]
@@ -105,9 +112,15 @@ async fn ws_listen<P: Param>(
     let s = Message::from(serde_json::to_string(&request).unwrap_or("".to_owned()));
     let _ = writer.send(s).await;
 
+    let mut heartbeat_secs = HEARTBEAT;
+    let mut heartbeat = tokio::time::interval(std::time::Duration::from_secs(heartbeat_secs));
     loop {
         let res = tokio::select! {
             v = async { in_recv.recv().await.map(|msg| WsResult::Out(msg)) } => v,
+            v = async {
+                heartbeat.tick().await;
+                Some(WsResult::Ping)
+            } => v,
             v = async {
                 reader
                     .next()
@@ -123,12 +136,49 @@ async fn ws_listen<P: Param>(
                 let s = Message::from(serde_json::to_string(&request).unwrap_or("".to_owned()));
                 let _ = writer.send(s).await;
             }
+            Some(WsResult::Ping) => {
+                let request = build_request(
+                    json!({
+                        "method": Z4_PING,
+                        "params": [],
+                    }),
+                    room,
+                    &peer,
+                );
+                let s = Message::from(serde_json::to_string(&request).unwrap_or("".to_owned()));
+                let _ = writer.send(s).await;
+            }
             Some(WsResult::Stream(msg)) => {
                 let msg = msg.to_text().unwrap_or("");
                 match serde_json::from_str::<Value>(&msg) {
                     Ok(mut values) => {
                         let gid = values["gid"].as_u64().unwrap_or(0);
                         let method = values["method"].as_str().unwrap_or("").to_owned();
+                        if method == Z4_PONG {
+                            // follow the idle timeout of server
+                            let idle_timeout = values["result"]["idle_timeout"].as_u64();
+                            if let Some(idle_timeout) = idle_timeout.filter(|t| *t > 0) {
+                                let secs = (idle_timeout / HEARTBEAT_TIMES).max(1);
+                                if secs != heartbeat_secs {
+                                    heartbeat_secs = secs;
+                                    let period = std::time::Duration::from_secs(secs);
+                                    let start = tokio::time::Instant::now() + period;
+                                    heartbeat = tokio::time::interval_at(start, period);
+                                }
+                            }
+                            continue;
+                        }
+                        // engine messages in reserved namespace, keep as method values
+                        if SystemMessage::is_system(&method) {
+                            let params = json!({
+                                "method": method,
+                                "params": values["result"].take(),
+                            });
+                            if let Ok(p) = P::from_value(params) {
+                                let _ = send.send((gid, p));
+                            }
+                            continue;
+                        }
                         let mut params = values["result"].take();
                         merge_json(
                             &mut params,
@@ -22,10 +22,12 @@ pub struct Room {
     players: Vec<PeerId>,
     /// room viewers
     viewers: HashMap<PeerId, ConnectType>,
+    /// room is started and locked, players cannot join or leave
+    started: bool,
 }
 
 impl Room {
-    /// Create a room
+    /// Create a room in lobby, start it when players are fixed
     pub fn new(id: RoomId, viewable: bool, peers: &[PeerId]) -> Self {
         let players = peers.to_vec();
         let viewers = if viewable {
@@ -39,19 +41,83 @@ impl Room {
             viewable,
             players,
             viewers,
+            started: false,
         }
     }
 
+    /// Check the room is started and locked
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Lock the room, return false if already started
+    pub fn start(&mut self) -> bool {
+        let changed = !self.started;
+        self.started = true;
+        changed
+    }
+
+    /// New player join the room when not started
+    pub fn join(&mut self, peer: PeerId) -> bool {
+        if self.started || self.players.contains(&peer) {
+            return false;
+        }
+        self.players.push(peer);
+        if !self.viewable {
+            self.viewers.insert(peer, ConnectType::None);
+        }
+        true
+    }
+
     /// Item the room viewers including the player
     pub fn iter(&self) -> Iter<PeerId, ConnectType> {
         self.viewers.iter()
     }
 
+    /// Item the room viewers which are not players
+    pub fn viewers(&self) -> impl Iterator<Item = (&PeerId, &ConnectType)> {
+        self.viewers.iter().filter(|(p, _)| !self.players.contains(p))
+    }
+
+    /// Check the room has viewers which are not players
+    pub fn has_viewers(&self) -> bool {
+        self.viewable && self.viewers().next().is_some()
+    }
+
+    /// Get the room players
+    pub fn players(&self) -> &[PeerId] {
+        &self.players
+    }
+
     /// Check peer is player
     pub fn is_player(&self, peer: &PeerId) -> bool {
         self.players.contains(peer)
     }
 
+    /// Player leave the room when not started, remove the seat
+    pub fn leave(&mut self, peer: &PeerId) -> bool {
+        if self.started || !self.players.contains(peer) {
+            return false;
+        }
+        self.players.retain(|p| p != peer);
+        self.viewers.remove(peer);
+        true
+    }
+
+    /// Replace the seat of player to new peer
+    pub fn replace(&mut self, old: &PeerId, new: PeerId) -> bool {
+        if let Some(seat) = self.players.iter_mut().find(|p| *p == old) {
+            *seat = new;
+            self.viewers.remove(old);
+            if !self.viewable {
+                self.viewers.insert(new, ConnectType::None);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
     /// Get the player/viewer connect type
     pub fn get(&self, peer: &PeerId) -> ConnectType {
         self.viewers
@@ -75,8 +141,10 @@ impl Room {
         }
     }
 
-    /// When player/viewer offline/disconnected
+    /// When player/viewer offline/disconnected, only existing player/viewer changed
     pub fn offline(&mut self, peer: PeerId) {
-        self.viewers.insert(peer, ConnectType::None);
+        if let Some(ctype) = self.viewers.get_mut(&peer) {
+            *ctype = ConnectType::None;
+        }
     }
 }
@@ -1,12 +1,15 @@
 use ethers::prelude::Address;
 use serde_json::{json, Value};
+use std::time::Instant;
 use tdn::{
     prelude::{PeerId, SendMessage},
-    types::rpc::rpc_response,
+    types::rpc::{rpc_response, RpcError},
 };
 use tokio::sync::mpsc::Sender;
 use z4_types::{
-    address_hex, Error, HandleResult, Handler, Param, Result, RoomId, Z4_ROOM_MARKET_GROUP,
+    address_hex, peer_to_address, Error, HandleResult, Handler, Param, Player, Result, RoomId,
+    SystemMessage, Z4_CHAT_HISTORY, Z4_CHAT_SEND, Z4_FORFEIT, Z4_JOIN, Z4_LEAVE, Z4_PING, Z4_PONG,
+    Z4_ROOM_MARKET_GROUP,
 };
 
 use crate::{engine::Engine, room::ConnectType};
@@ -23,6 +26,20 @@ pub async fn handle_rpc<H: Handler>(
     let gid = params["gid"].as_u64().unwrap_or(0);
     let method = params["method"].as_str().unwrap_or("").to_owned();
     let peer_id = PeerId::from_hex(params["peer"].as_str().unwrap_or(""))?;
+    // the json text is parsed by the rpc layer, the request is measured before any use of it
+    let size = serde_json::to_vec(&params).map(|v| v.len()).unwrap_or(0);
+
+    if is_ws {
+        engine.heartbeat(uid, peer_id);
+    }
+
+    // inner rpc method for keep the websocket connection alive, no room required
+    if method == Z4_PING {
+        let idle_timeout = engine.config.idle_timeout;
+        let rpc_msg = rpc_response(id, Z4_PONG, json!({ "idle_timeout": idle_timeout }), gid);
+        let _ = send.send(SendMessage::Rpc(uid, rpc_msg, is_ws)).await;
+        return Ok(None);
+    }
 
     // inner rpc method for query all pending room for a game
     if &method == "room_market" && gid == Z4_ROOM_MARKET_GROUP {
@@ -71,15 +88,101 @@ pub async fn handle_rpc<H: Handler>(
         return Ok(None);
     }
 
+    // inner rpc method for query the room lifecycle status
+    if &method == "room_status" {
+        let status = engine.room_status(&gid).ok_or(Error::NoRoom)?;
+        let mut value = status.to_value();
+        value["room"] = gid.into();
+        let rpc_msg = rpc_response(id, &method, value, gid);
+        let _ = send.send(SendMessage::Rpc(uid, rpc_msg, is_ws)).await;
+
+        return Ok(None);
+    }
+
+    // inner rpc method for query the active sequencers
+    if &method == "sequencers" {
+        let rpc_msg = rpc_response(id, &method, engine.sequencers().to_value(), gid);
+        let _ = send.send(SendMessage::Rpc(uid, rpc_msg, is_ws)).await;
+
+        return Ok(None);
+    }
+
     if !engine.has_room(&gid) {
         return Err(Error::NoRoom);
     }
 
+    if let Err(err) = engine.check_limit(gid, peer_id, size) {
+        if engine.is_offender(gid, &peer_id) {
+            warn!("Disconnect offender {:?} in room {}", peer_id, gid);
+            let msg = RpcError::Custom(format!("{:?}", err)).json(id);
+            let _ = send.send(SendMessage::Rpc(uid, msg, is_ws)).await;
+
+            let res = engine.kick_offline(gid, peer_id).await?;
+            return Ok(res.map(|res| (res, gid, None, id)));
+        }
+        return Err(err);
+    }
+
+    // inner rpc methods in reserved namespace, never passed to the game handler
+    if SystemMessage::is_system(&method) {
+        let res = match method.as_str() {
+            Z4_CHAT_SEND => {
+                let text = params["params"][0]
+                    .as_str()
+                    .ok_or(Error::Params)?
+                    .to_owned();
+                engine.chat_send(gid, peer_id, text, send).await?;
+                let rpc_msg = rpc_response(id, &method, json!(true), gid);
+                let _ = send.send(SendMessage::Rpc(uid, rpc_msg, is_ws)).await;
+                return Ok(None);
+            }
+            Z4_CHAT_HISTORY => {
+                let history = engine.chat_history(gid, peer_id)?.to_values();
+                let rpc_msg = rpc_response(id, &method, history.to_value(), gid);
+                let _ = send.send(SendMessage::Rpc(uid, rpc_msg, is_ws)).await;
+                return Ok(None);
+            }
+            // player join the room when it is not started
+            Z4_JOIN => {
+                // the signer public key of player, checked by the game which requires keys
+                let mut signer = [0u8; 32];
+                let hex_signer = params["signer"].as_str().unwrap_or("");
+                if let Ok(bytes) = hex::decode(hex_signer.trim_start_matches("0x")) {
+                    if bytes.len() == 32 {
+                        signer.copy_from_slice(&bytes);
+                    }
+                }
+                let player = Player::new(peer_to_address(peer_id), peer_id, signer);
+                let data = serde_json::to_vec(&params["params"])?;
+                let res = engine.join_room(gid, player, data).await?;
+                if is_ws {
+                    engine.online(gid, peer_id, ConnectType::Rpc(uid)).await;
+                }
+                res
+            }
+            Z4_LEAVE => engine.leave_room(gid, peer_id).await?,
+            Z4_FORFEIT => engine.forfeit_room(gid, peer_id).await?,
+            _ => return Err(Error::Params),
+        };
+
+        let is_rpc = if is_ws { None } else { Some((peer_id, uid)) };
+        return Ok(Some((res, gid, is_rpc, id)));
+    }
+
     if &method == "connect" && is_ws {
         if engine.online(gid, peer_id, ConnectType::Rpc(uid)).await {
-            let mut handler = engine.get_room(&gid).handler.lock().await;
-            let res = handler.online(peer_id).await?;
+            let hr = engine.get_room(&gid);
+            let ctx = hr.context.read().await.clone();
+            let mut handler = hr.handler.lock().await;
+            let res = if hr.room.is_player(&peer_id) {
+                handler.online(&ctx, peer_id).await?
+            } else {
+                handler.viewer_online(&ctx, peer_id).await?
+            };
             drop(handler);
+            if !hr.room.is_player(&peer_id) {
+                engine.send_snapshot(gid, peer_id, send).await;
+            }
 
             let is_rpc = if is_ws { None } else { Some((peer_id, uid)) };
             return Ok(Some((res, gid, is_rpc, id)));
@@ -94,9 +197,14 @@ pub async fn handle_rpc<H: Handler>(
     let param = H::Param::from_value(params)?;
 
     if engine.is_room_player(&gid, &peer_id).await {
-        let mut handler = engine.get_room(&gid).handler.lock().await;
-        let res = handler.handle(peer_id, param).await?;
+        let start = Instant::now();
+        let hr = engine.get_room(&gid);
+        let ctx = hr.context.read().await.clone();
+        let mut handler = hr.handler.lock().await;
+        let res = handler.handle(&ctx, peer_id, param).await;
         drop(handler);
+        engine.metrics.handler_latency.observe(start.elapsed());
+        let res = res?;
 
         let is_rpc = if is_ws { None } else { Some((peer_id, uid)) };
         return Ok(Some((res, gid, is_rpc, id)));
@@ -1,19 +1,32 @@
-use anyhow::Result;
-use ark_serialize::{CanonicalDeserialize, Compress, Validate};
-use ethers::prelude::*;
-use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use ethers::{abi::RawLog, prelude::*};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{atomic::Ordering, Arc};
 use std::time::Duration;
 use tokio::{
-    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    select,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        Mutex, Notify,
+    },
     time::timeout,
 };
-use z4_types::{PeerId, PublicKey, RoomId};
+use z4_types::{chain_room_id, PeerId, Player, RoomId};
 
-use crate::contracts::RoomMarket;
+use crate::metrics::Metrics;
+use crate::storage::Storage;
 use crate::ChainMessage;
 
 const TIMEOUT: u64 = 10;
 const DELAY: u64 = 1;
+/// Max scanned block hashes kept for reorg detection
+const KEEP_HASHES: usize = 128;
+/// Max blocks of logs kept for the lagging providers behind the quorum checkpoint
+const KEEP_BLOCKS: u64 = 1024;
+/// Seconds between two polling when logs subscribed by websocket
+const GAP_FILL: u64 = 30;
+/// Blocks of one logs query when backfill the sequencers
+const BACKFILL_BLOCKS: u64 = 5000;
 
 #[derive(Clone, Debug, EthEvent)]
 struct CreateRoom {
@@ -56,6 +69,215 @@ struct OverRoom {
     room: U256,
 }
 
+#[derive(Clone, Debug, EthEvent)]
+struct ClaimRoom {
+    room: U256,
+}
+
+#[derive(Clone, Debug, EthEvent)]
+struct StakeSequencer {
+    sequencer: Address,
+    http: String,
+    websocket: String,
+    staking: U256,
+}
+
+#[derive(Clone, Debug, EthEvent)]
+struct UnstakeSequencer {
+    sequencer: Address,
+    staking: U256,
+}
+
+/// Logs which had been sent to engine, need revert when reorg.
+/// Only the pending rooms are rolled back, the reverted start, accept and over are warned,
+/// the transactions of them are usually mined again, and the room follows the new logs
+enum Scanned {
+    Create(RoomId),
+    Join(RoomId, PeerId),
+    Start(RoomId),
+    Accept(RoomId),
+    Over(RoomId),
+}
+
+/// Scanned block hashes of a provider
+#[derive(Default)]
+struct Tracker {
+    hashes: BTreeMap<u64, H256>,
+}
+
+impl Tracker {
+    fn record(&mut self, block: u64, hash: H256) {
+        self.hashes.insert(block, hash);
+        while self.hashes.len() > KEEP_HASHES {
+            self.hashes.pop_first();
+        }
+    }
+
+    fn rollback(&mut self, ancestor: u64) {
+        self.hashes.split_off(&(ancestor + 1));
+    }
+}
+
+/// The log reported by providers
+struct Reported {
+    /// the providers which reported this log
+    reporters: HashSet<usize>,
+    /// the log waiting for quorum, none when it had been sent
+    log: Option<Log>,
+    /// the sent log which need revert when reorg
+    scanned: Option<Scanned>,
+}
+
+/// Logs which reported by all providers and subscriptions,
+/// by block and (transaction, log index)
+struct Forwarded {
+    /// the namespace of chain market and room ids
+    chain: usize,
+    /// the number of providers which report the same log before it is sent
+    quorum: usize,
+    logs: BTreeMap<u64, HashMap<(H256, U256), Reported>>,
+    /// the logs before this block had been pruned, the late reports of them are ignored
+    pruned: u64,
+}
+
+impl Forwarded {
+    fn new(chain: usize, quorum: usize) -> Self {
+        Self {
+            chain,
+            quorum: quorum.max(1),
+            logs: BTreeMap::new(),
+            pruned: 0,
+        }
+    }
+
+    /// Report the log from provider, send it to engine when reach the quorum
+    fn forward(
+        &mut self,
+        source: usize,
+        log: Log,
+        sender: &UnboundedSender<ChainMessage>,
+    ) -> Result<()> {
+        let (block, key) = match log_position(&log) {
+            Some(position) if position.0 >= self.pruned => position,
+            _ => return Ok(()),
+        };
+
+        let reported = self
+            .logs
+            .entry(block)
+            .or_default()
+            .entry(key)
+            .or_insert_with(|| Reported {
+                reporters: HashSet::new(),
+                log: Some(log),
+                scanned: None,
+            });
+        reported.reporters.insert(source);
+        if reported.reporters.len() >= self.quorum {
+            if let Some(log) = reported.log.take() {
+                reported.scanned = handle_log(self.chain, log, sender)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Prune the logs which all providers scanned, the reorg depth before it is kept
+    fn prune(&mut self, scanned: u64) {
+        let pruned = scanned.saturating_sub(KEEP_HASHES as u64);
+        if pruned > self.pruned {
+            self.pruned = pruned;
+            self.logs = self.logs.split_off(&pruned);
+        }
+    }
+
+    /// Revert the log which removed from chain
+    fn revert(&mut self, log: &Log, sender: &UnboundedSender<ChainMessage>) -> Result<()> {
+        if let Some((block, key)) = log_position(log) {
+            let reported = self.logs.get_mut(&block).and_then(|v| v.remove(&key));
+            if let Some(scanned) = reported.and_then(|r| r.scanned) {
+                send_revert(scanned, sender)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rollback to the ancestor block, revert the sent logs after it, latest first
+    fn rollback(&mut self, ancestor: u64, sender: &UnboundedSender<ChainMessage>) -> Result<()> {
+        let reverted = self.logs.split_off(&(ancestor + 1));
+        for (_, logs) in reverted.into_iter().rev() {
+            for scanned in logs.into_values().filter_map(|r| r.scanned) {
+                send_revert(scanned, sender)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The logs in the blocks which reported by others but not by this provider
+    fn disagreements(&self, source: usize, from: u64, to: u64) -> Vec<(u64, (H256, U256))> {
+        let mut missing = vec![];
+        for (&block, logs) in self.logs.range(from..=to) {
+            for (key, reported) in logs {
+                if !reported.reporters.contains(&source) {
+                    missing.push((block, *key));
+                }
+            }
+        }
+        missing
+    }
+}
+
+#[inline]
+fn log_position(log: &Log) -> Option<(u64, (H256, U256))> {
+    Some((
+        log.block_number?.as_u64(),
+        (log.transaction_hash?, log.log_index?),
+    ))
+}
+
+fn send_revert(scanned: Scanned, sender: &UnboundedSender<ChainMessage>) -> Result<()> {
+    match scanned {
+        Scanned::Create(rid) => sender.send(ChainMessage::RevertCreateRoom(rid))?,
+        Scanned::Join(rid, peer) => sender.send(ChainMessage::RevertJoinRoom(rid, peer))?,
+        Scanned::Start(rid) => warn!("Scan: start of room {} reverted by reorg", rid),
+        Scanned::Accept(rid) => warn!("Scan: accept of room {} reverted by reorg", rid),
+        Scanned::Over(rid) => warn!("Scan: over of room {} reverted by reorg", rid),
+    }
+    Ok(())
+}
+
+/// The filter of all RoomMarket events which engine need
+fn market_filter(market: Address) -> Filter {
+    Filter::new().address(market).topic0(vec![
+        CreateRoom::signature(),
+        JoinRoom::signature(),
+        StartRoom::signature(),
+        AcceptRoom::signature(),
+        OverRoom::signature(),
+        ClaimRoom::signature(),
+        StakeSequencer::signature(),
+        UnstakeSequencer::signature(),
+    ])
+}
+
+/// The storage key of scan checkpoint, by chain id and market address
+#[inline]
+fn checkpoint_key(chain_id: U256, market: Address) -> String {
+    format!("scan-{}-{:?}", chain_id, market)
+}
+
+/// Load the last fully processed block
+fn load_checkpoint(storage: &dyn Storage, key: &str) -> Option<u64> {
+    let bytes = storage.get(key)?;
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// Save the last fully processed block
+fn save_checkpoint(storage: &dyn Storage, key: &str, block: u64) {
+    if let Err(err) = storage.put(key, block.to_be_bytes().to_vec()) {
+        error!("Scan checkpoint: {:?}", err);
+    }
+}
+
 /// Create scan channel
 pub fn chain_channel() -> (
     UnboundedSender<ChainMessage>,
@@ -64,36 +286,106 @@ pub fn chain_channel() -> (
     unbounded_channel()
 }
 
-/// Listen scan task
+/// The scan config of a chain market
+pub struct ScanConfig {
+    /// the namespace of room ids
+    pub chain: usize,
+    /// http providers for scan
+    pub clients: Vec<Arc<Provider<Http>>>,
+    /// websocket urls to subscribe logs
+    pub ws_urls: Vec<String>,
+    /// room market address
+    pub market: Address,
+    /// scan start block, override the saved checkpoint
+    pub start: Option<u64>,
+    /// the block which market deployed, none is searched from the providers
+    pub deploy: Option<u64>,
+    /// blocks of confirmation when scan
+    pub confirmations: u64,
+    /// the number of providers which must report a log before forwarded
+    pub quorum: usize,
+}
+
+/// Listen scan task, logs will be subscribed when has websocket urls,
+/// and the http providers only fill the gaps
 pub async fn listen(
-    clients: Vec<Arc<Provider<Http>>>,
-    market_address: Address,
+    mut config: ScanConfig,
     sender: UnboundedSender<ChainMessage>,
-    start: Option<u64>,
+    storage: Arc<dyn Storage>,
+    metrics: Arc<Metrics>,
 ) -> Result<()> {
-    let markets: Vec<_> = clients
-        .iter()
-        .map(|client| RoomMarket::new(market_address, client.clone()))
-        .collect();
+    let (chain, market_address) = (config.chain, config.market);
+    let (clients, quorum) = (&config.clients, config.quorum);
+    let ws_urls = std::mem::take(&mut config.ws_urls);
+    let sources = clients.len() + ws_urls.len();
+    if quorum > sources {
+        warn!("Scan quorum {} more than {} providers", quorum, sources);
+    }
+    let forwarded = Arc::new(Mutex::new(Forwarded::new(chain, quorum.min(sources))));
+    let refill = if ws_urls.is_empty() {
+        None
+    } else {
+        Some(Arc::new(Notify::new()))
+    };
+    if let Some(notify) = &refill {
+        for (i, url) in ws_urls.into_iter().enumerate() {
+            tokio::spawn(subscribe(
+                clients.len() + i,
+                url,
+                market_address,
+                forwarded.clone(),
+                sender.clone(),
+                config.confirmations,
+                notify.clone(),
+            ));
+        }
+    }
 
+    let mut key = None;
+    let mut backfilled = false;
     let mut next_index = 0;
     loop {
-        let start_block = if start.is_some() {
-            start
-        } else {
-            if let Ok(start_block) = clients[next_index].get_block_number().await {
-                Some(start_block.as_u64() - DELAY)
-            } else {
-                None
+        if key.is_none() {
+            if let Ok(chain_id) = clients[next_index].get_chainid().await {
+                key = Some(checkpoint_key(chain_id, market_address));
             }
+        }
+
+        // the start block from env is override, only use it at first time when can scan
+        let start_block = match (&key, config.start) {
+            (None, _) => None,
+            (Some(_), Some(start_block)) => Some(start_block),
+            (Some(key), None) => match load_checkpoint(storage.as_ref(), key) {
+                Some(checkpoint) => Some(checkpoint),
+                None => clients[next_index]
+                    .get_block_number()
+                    .await
+                    .ok()
+                    .map(|head| head.as_u64().saturating_sub(DELAY + config.confirmations)),
+            },
         };
 
-        if let Some(start_block) = start_block {
+        // sequencers registry is not saved, rebuild it from the logs before start
+        if let (Some(start_block), false) = (start_block, backfilled) {
+            let client = &clients[next_index];
+            let deploy = config.deploy;
+            match backfill(chain, client, market_address, deploy, start_block, &sender).await {
+                Ok(()) => backfilled = true,
+                Err(err) => error!("Backfill sequencers: {}, or set DEPLOY_BLOCK", err),
+            }
+        }
+
+        // not scan before the sequencers backfilled, retry with next provider
+        if let (Some(start_block), Some(key), true) = (start_block, &key, backfilled) {
+            config.start = None;
+            info!("Scan start from {}", start_block);
             let _ = running(
+                &config,
                 start_block,
-                clients.clone(),
-                markets.clone(),
-                sender.clone(),
+                &sender,
+                (storage.clone(), key.clone()),
+                (forwarded.clone(), refill.clone()),
+                metrics.clone(),
             )
             .await;
         }
@@ -108,38 +400,139 @@ pub async fn listen(
     }
 }
 
+/// The block which market deployed, the first block has its code,
+/// the state of old blocks is required, so the pruned providers will be failure
+async fn deploy_block(client: &Provider<Http>, market: Address, head: u64) -> Result<u64> {
+    let (mut low, mut high) = (0, head);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let code = timeout(
+            Duration::from_secs(TIMEOUT),
+            client.get_code(market, Some(mid.into())),
+        )
+        .await
+        .map_err(|_| anyhow!("Timeout"))??;
+        if code.is_empty() {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    Ok(low)
+}
+
+/// Send the stake/unstake logs from the market deployed to the end block
+async fn backfill(
+    chain: usize,
+    client: &Provider<Http>,
+    market: Address,
+    deploy: Option<u64>,
+    end: u64,
+    sender: &UnboundedSender<ChainMessage>,
+) -> Result<()> {
+    let mut from = match deploy {
+        Some(block) => block,
+        None => deploy_block(client, market, end).await?,
+    };
+    info!("Backfill sequencers from {} to {}", from, end);
+    while from <= end {
+        let to = (from + BACKFILL_BLOCKS - 1).min(end);
+        let filter = Filter::new()
+            .address(market)
+            .topic0(vec![
+                StakeSequencer::signature(),
+                UnstakeSequencer::signature(),
+            ])
+            .from_block(from)
+            .to_block(to);
+        let mut logs = timeout(Duration::from_secs(TIMEOUT), client.get_logs(&filter))
+            .await
+            .map_err(|_| anyhow!("Timeout"))??;
+        logs.sort_by_key(|log| (log.block_number, log.log_index));
+        for log in logs {
+            handle_log(chain, log, sender)?;
+        }
+        from = to + 1;
+    }
+    Ok(())
+}
+
 /// Loop running scan task
-pub async fn running(
+async fn running(
+    config: &ScanConfig,
     start_block: u64,
-    clients: Vec<Arc<Provider<Http>>>,
-    markets: Vec<RoomMarket<Provider<Http>>>,
-    sender: UnboundedSender<ChainMessage>,
+    sender: &UnboundedSender<ChainMessage>,
+    checkpoint: (Arc<dyn Storage>, String),
+    shared: (Arc<Mutex<Forwarded>>, Option<Arc<Notify>>),
+    metrics: Arc<Metrics>,
 ) -> Result<()> {
+    let (chain, market, confirmations) = (config.chain, config.market, config.confirmations);
+    let clients = &config.clients;
+    let (storage, key) = checkpoint;
+    let (forwarded, refill) = shared;
     let clients_len = clients.len();
+    let quorum = forwarded.lock().await.quorum.min(clients_len).max(1);
 
     let mut starts: Vec<_> = vec![start_block; clients_len];
+    let mut trackers: Vec<Tracker> = (0..clients_len).map(|_| Tracker::default()).collect();
+    let mut height = 0;
     let mut i = 0;
     loop {
         i += 1;
         i = if i < clients_len { i } else { 0 };
 
-        let start = starts[i];
         let end_res = if let Ok(res) =
             timeout(Duration::from_secs(TIMEOUT), clients[i].get_block_number()).await
         {
             res
         } else {
             warn!("Timeout: {}", i);
+            metrics.scan_timeouts.fetch_add(1, Ordering::Relaxed);
             continue;
         };
         if let Err(err) = end_res {
             error!("{}", err);
             continue;
         }
-        let mut end = end_res.unwrap().as_u64() - DELAY; // safe
+        let head = end_res.unwrap().as_u64(); // safe
+
+        // check the latest scanned block is still canonical
+        match timeout(
+            Duration::from_secs(TIMEOUT),
+            find_ancestor(&clients[i], &trackers[i]),
+        )
+        .await
+        {
+            Ok(Ok(Some(ancestor))) => {
+                warn!("Reorg {} detected, rollback to {}", i, ancestor);
+                metrics.scan_reorgs.fetch_add(1, Ordering::Relaxed);
+                // the reverted logs maybe reported by others, all providers scan them again
+                for (start, tracker) in starts.iter_mut().zip(trackers.iter_mut()) {
+                    *start = (*start).min(ancestor);
+                    tracker.rollback(ancestor);
+                }
+                forwarded.lock().await.rollback(ancestor, sender)?;
+                save_checkpoint(storage.as_ref(), &key, ancestor);
+                continue;
+            }
+            Ok(Ok(None)) => {}
+            Ok(Err(err)) => {
+                error!("{}", err);
+                continue;
+            }
+            Err(_) => {
+                warn!("Timeout: {}", i);
+                metrics.scan_timeouts.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+        }
+
+        let start = starts[i];
+        let mut end = head.saturating_sub(DELAY + confirmations);
         if start == end {
+            metrics.scan_lag(chain, i, head.saturating_sub(start));
             debug!("start {} == {} end", start, end);
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            waiting(&refill).await;
             continue;
         }
         if end > start && end - start > 200 {
@@ -153,192 +546,335 @@ pub async fn running(
             (start + 1, end)
         };
 
-        let create_room = markets[i]
-            .event::<CreateRoom>()
-            .from_block(from)
-            .to_block(to);
-
-        let join_room = markets[i].event::<JoinRoom>().from_block(from).to_block(to);
-
-        let start_room = markets[i]
-            .event::<StartRoom>()
-            .from_block(from)
-            .to_block(to);
-
-        let accept_room = markets[i]
-            .event::<AcceptRoom>()
-            .from_block(from)
-            .to_block(to);
-
-        let over_room = markets[i].event::<OverRoom>().from_block(from).to_block(to);
-
-        let creates_room =
-            if let Ok(res) = timeout(Duration::from_secs(TIMEOUT), create_room.query()).await {
-                res
-            } else {
-                warn!("Timeout: {}", i);
+        // the hash of end block, for next reorg check
+        let end_block = timeout(Duration::from_secs(TIMEOUT), clients[i].get_block(to)).await;
+        let end_hash = match end_block {
+            Ok(Ok(Some(block))) => block.hash,
+            Ok(Ok(None)) => None,
+            Ok(Err(err)) => {
+                error!("{}", err);
                 continue;
-            };
-
-        let joins_room =
-            if let Ok(res) = timeout(Duration::from_secs(TIMEOUT), join_room.query()).await {
-                res
-            } else {
+            }
+            Err(_) => {
                 warn!("Timeout: {}", i);
+                metrics.scan_timeouts.fetch_add(1, Ordering::Relaxed);
                 continue;
-            };
+            }
+        };
 
-        let starts_room =
-            if let Ok(res) = timeout(Duration::from_secs(TIMEOUT), start_room.query()).await {
-                res
-            } else {
-                warn!("Timeout: {}", i);
-                continue;
-            };
+        let filter = market_filter(market).from_block(from).to_block(to);
 
-        let accepts_room =
-            if let Ok(res) = timeout(Duration::from_secs(TIMEOUT), accept_room.query()).await {
-                res
-            } else {
-                warn!("Timeout: {}", i);
+        let query = timeout(Duration::from_secs(TIMEOUT), clients[i].get_logs(&filter)).await;
+        let mut logs = match query {
+            Ok(Ok(logs)) => logs,
+            Ok(Err(err)) => {
+                error!("{}", err);
                 continue;
-            };
-
-        let overs_room =
-            if let Ok(res) = timeout(Duration::from_secs(TIMEOUT), over_room.query()).await {
-                res
-            } else {
+            }
+            Err(_) => {
                 warn!("Timeout: {}", i);
+                metrics.scan_timeouts.fetch_add(1, Ordering::Relaxed);
                 continue;
-            };
-
-        if let Ok(creates) = creates_room {
-            for create in creates {
-                let CreateRoom {
-                    room,
-                    game,
-                    reward,
-                    viewable,
-                    player,
-                    peer,
-                    pk,
-                    salt,
-                    block,
-                } = create;
-                info!(
-                    "scan create: {} {} {} {} {}",
-                    room, game, reward, viewable, player
-                );
+            }
+        };
 
-                match (parse_room(room), parse_peer(peer)) {
-                    (Some(rid), Some(peer)) => {
-                        sender.send(ChainMessage::CreateRoom(
-                            rid,
-                            game,
-                            viewable,
-                            player,
-                            peer,
-                            pk.to_fixed_bytes(),
-                            salt.to_fixed_bytes(),
-                            block.to_fixed_bytes(),
-                        ))?;
-                    }
-                    _ => continue,
-                }
+        // handle the logs as the order on chain
+        logs.sort_by_key(|log| (log.block_number, log.log_index));
+        let mut forwarded_lock = forwarded.lock().await;
+        for log in logs {
+            if let Err(err) = forwarded_lock.forward(i, log, sender) {
+                error!("Scan log: {}", err);
             }
         }
+        let missing = forwarded_lock.disagreements(i, from, to);
+        drop(forwarded_lock);
+        if !missing.is_empty() {
+            for (block, (tx, index)) in missing.iter() {
+                warn!("Scan {} missing log: {} {:?} {}", i, block, tx, index);
+            }
+            metrics.scan_disagree(chain, i, missing.len() as u64);
+        }
 
-        if let Ok(joins) = joins_room {
-            for join in joins {
-                let JoinRoom {
-                    room,
-                    player,
-                    peer,
-                    pk,
-                } = join;
-                info!("scan join: {} {}", room, player);
-
-                match (parse_room(room), parse_peer(peer)) {
-                    (Some(rid), Some(peer)) => {
-                        sender.send(ChainMessage::JoinRoom(
-                            rid,
-                            player,
-                            peer,
-                            pk.to_fixed_bytes(),
-                        ))?;
-                    }
-                    _ => continue,
-                }
+        starts[i] = end;
+        if let Some(hash) = end_hash {
+            trackers[i].record(to, hash);
+        }
+        // the lowest start of the quorum most advanced providers, logs before it reached quorum
+        let mut processed = starts.clone();
+        processed.sort_unstable_by(|a, b| b.cmp(a));
+        if let Some(&processed) = processed.get(quorum - 1) {
+            save_checkpoint(storage.as_ref(), &key, processed);
+            if processed > height {
+                height = processed;
+                sender.send(ChainMessage::Height(chain, height))?;
             }
+            // the late reports of lagging providers maybe reach the quorum, keep them a while
+            let lowest = starts.iter().min().copied().unwrap_or(processed);
+            let scanned = lowest.max(processed.saturating_sub(KEEP_BLOCKS));
+            forwarded.lock().await.prune(scanned);
+        }
+        metrics.scan_lag(chain, i, head.saturating_sub(end));
+
+        waiting(&refill).await;
+    }
+}
+
+/// Waiting for next polling, it will be woken up when subscription reconnect
+async fn waiting(refill: &Option<Arc<Notify>>) {
+    match refill {
+        Some(notify) => {
+            let _ = timeout(Duration::from_secs(GAP_FILL), notify.notified()).await;
         }
+        None => tokio::time::sleep(Duration::from_secs(1)).await,
+    }
+}
 
-        if let Ok(starts) = starts_room {
-            for start in starts {
-                let StartRoom { room, game } = start;
-                info!("scan start: {} {} ", room, game);
+/// Subscribe logs from the websocket provider, reconnect when failure
+async fn subscribe(
+    source: usize,
+    url: String,
+    market: Address,
+    forwarded: Arc<Mutex<Forwarded>>,
+    sender: UnboundedSender<ChainMessage>,
+    confirmations: u64,
+    refill: Arc<Notify>,
+) {
+    loop {
+        let res = subscribing(source, &url, market, &forwarded, &sender, confirmations).await;
+        if let Err(err) = res {
+            error!("Scan subscription {}: {}", url, err);
+        }
+
+        // logs maybe lost when disconnected, fill the gap by polling
+        refill.notify_one();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+}
 
-                match parse_room(room) {
-                    Some(rid) => {
-                        sender.send(ChainMessage::StartRoom(rid, game))?;
+/// Running the logs subscription, logs will be sent after confirmations
+async fn subscribing(
+    source: usize,
+    url: &str,
+    market: Address,
+    forwarded: &Mutex<Forwarded>,
+    sender: &UnboundedSender<ChainMessage>,
+    confirmations: u64,
+) -> Result<()> {
+    let client = Provider::<Ws>::connect(url).await?;
+    let mut logs = client.subscribe_logs(&market_filter(market)).await?;
+    let mut heads = client.subscribe_blocks().await?;
+    info!("Scan subscribed: {}", url);
+
+    // logs which waiting for confirmations, by block and log index
+    let mut unconfirmed: BTreeMap<(u64, U256), Log> = BTreeMap::new();
+    loop {
+        select! {
+            log = logs.next() => {
+                let log = log.ok_or_else(|| anyhow!("logs subscription closed"))?;
+                let position = if let Some((block, (_, index))) = log_position(&log) {
+                    (block, index)
+                } else {
+                    continue;
+                };
+
+                if log.removed == Some(true) {
+                    if unconfirmed.remove(&position).is_none() {
+                        forwarded.lock().await.revert(&log, sender)?;
+                    }
+                } else if confirmations == 0 {
+                    if let Err(err) = forwarded.lock().await.forward(source, log, sender) {
+                        error!("Scan log: {}", err);
                     }
-                    _ => continue,
+                } else {
+                    unconfirmed.insert(position, log);
                 }
             }
-        }
-
-        if let Ok(accepts) = accepts_room {
-            for accept in accepts {
-                let AcceptRoom {
-                    room,
-                    sequencer,
-                    websocket,
-                    locked,
-                    params,
-                } = accept;
-                info!(
-                    "scan accept: {} {} {} {}",
-                    room, sequencer, websocket, locked
-                );
-                match (parse_room(room), parse_peer(sequencer)) {
-                    (Some(rid), Some(pid)) => {
-                        sender.send(ChainMessage::AcceptRoom(
-                            rid,
-                            pid,
-                            websocket,
-                            params.to_vec(),
-                        ))?;
+            head = heads.next() => {
+                let head = head.ok_or_else(|| anyhow!("blocks subscription closed"))?;
+                if let Some(number) = head.number {
+                    let confirmed = number.as_u64().saturating_sub(confirmations);
+                    let waiting = unconfirmed.split_off(&(confirmed + 1, U256::zero()));
+                    let logs = std::mem::replace(&mut unconfirmed, waiting);
+
+                    let mut forwarded_lock = forwarded.lock().await;
+                    for (_, log) in logs {
+                        if let Err(err) = forwarded_lock.forward(source, log, sender) {
+                            error!("Scan log: {}", err);
+                        }
                     }
-                    _ => continue,
                 }
             }
         }
+    }
+}
 
-        if let Ok(overs) = overs_room {
-            for over in overs {
-                let OverRoom { room } = over;
-                info!("scan over: {}", room);
-                match parse_room(room) {
-                    Some(rid) => {
-                        sender.send(ChainMessage::ChainOverRoom(rid))?;
-                    }
-                    _ => continue,
-                }
+/// Decode the log and send to engine, return the log which need revert when reorg
+fn handle_log(
+    chain: usize,
+    log: Log,
+    sender: &UnboundedSender<ChainMessage>,
+) -> Result<Option<Scanned>> {
+    let topic = if let Some(topic) = log.topics.first() {
+        *topic
+    } else {
+        return Ok(None);
+    };
+    let raw = RawLog::from(log);
+
+    if topic == CreateRoom::signature() {
+        let CreateRoom {
+            room,
+            game,
+            reward,
+            viewable,
+            player,
+            peer,
+            pk,
+            salt,
+            block,
+        } = <CreateRoom as EthEvent>::decode_log(&raw)?;
+        info!(
+            "scan create: {} {} {} {} {}",
+            room, game, reward, viewable, player
+        );
+
+        if let (Some(rid), Some(peer)) = (parse_room(chain, room), parse_peer(peer)) {
+            let player = Player::new(player, peer, pk.to_fixed_bytes());
+            if player.pk.is_none() {
+                warn!(
+                    "Scan: player {} has invalid key in room {}",
+                    player.account, rid
+                );
             }
+            sender.send(ChainMessage::CreateRoom(
+                rid,
+                game,
+                viewable,
+                player,
+                salt.to_fixed_bytes(),
+                block.to_fixed_bytes(),
+            ))?;
+            return Ok(Some(Scanned::Create(rid)));
         }
+    } else if topic == JoinRoom::signature() {
+        let JoinRoom {
+            room,
+            player,
+            peer,
+            pk,
+        } = <JoinRoom as EthEvent>::decode_log(&raw)?;
+        info!("scan join: {} {}", room, player);
+
+        if let (Some(rid), Some(peer)) = (parse_room(chain, room), parse_peer(peer)) {
+            let player = Player::new(player, peer, pk.to_fixed_bytes());
+            if player.pk.is_none() {
+                warn!(
+                    "Scan: player {} has invalid key in room {}",
+                    player.account, rid
+                );
+            }
+            sender.send(ChainMessage::JoinRoom(rid, player))?;
+            return Ok(Some(Scanned::Join(rid, peer)));
+        }
+    } else if topic == StartRoom::signature() {
+        let StartRoom { room, game } = <StartRoom as EthEvent>::decode_log(&raw)?;
+        info!("scan start: {} {} ", room, game);
 
-        starts[i] = end;
+        if let Some(rid) = parse_room(chain, room) {
+            sender.send(ChainMessage::StartRoom(rid, game))?;
+            return Ok(Some(Scanned::Start(rid)));
+        }
+    } else if topic == AcceptRoom::signature() {
+        let AcceptRoom {
+            room,
+            sequencer,
+            websocket,
+            locked,
+            params,
+        } = <AcceptRoom as EthEvent>::decode_log(&raw)?;
+        info!(
+            "scan accept: {} {} {} {}",
+            room, sequencer, websocket, locked
+        );
+
+        if let (Some(rid), Some(pid)) = (parse_room(chain, room), parse_peer(sequencer)) {
+            sender.send(ChainMessage::AcceptRoom(
+                rid,
+                pid,
+                websocket,
+                params.to_vec(),
+            ))?;
+            return Ok(Some(Scanned::Accept(rid)));
+        }
+    } else if topic == OverRoom::signature() {
+        let OverRoom { room } = <OverRoom as EthEvent>::decode_log(&raw)?;
+        info!("scan over: {}", room);
 
-        // waiting 1s
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        if let Some(rid) = parse_room(chain, room) {
+            sender.send(ChainMessage::ChainOverRoom(rid))?;
+            return Ok(Some(Scanned::Over(rid)));
+        }
+    } else if topic == ClaimRoom::signature() {
+        let ClaimRoom { room } = <ClaimRoom as EthEvent>::decode_log(&raw)?;
+        info!("scan claim: {}", room);
+
+        if let Some(rid) = parse_room(chain, room) {
+            sender.send(ChainMessage::ClaimRoom(rid))?;
+        }
+    } else if topic == StakeSequencer::signature() {
+        let StakeSequencer {
+            sequencer,
+            http,
+            websocket,
+            staking,
+        } = <StakeSequencer as EthEvent>::decode_log(&raw)?;
+        info!(
+            "scan stake: {} {} {} {}",
+            sequencer, http, websocket, staking
+        );
+
+        if let Some(pid) = parse_peer(sequencer) {
+            sender.send(ChainMessage::StakeSequencer(
+                chain, pid, http, websocket, staking,
+            ))?;
+        }
+    } else if topic == UnstakeSequencer::signature() {
+        let UnstakeSequencer { sequencer, staking } =
+            <UnstakeSequencer as EthEvent>::decode_log(&raw)?;
+        info!("scan unstake: {} {}", sequencer, staking);
+
+        if let Some(pid) = parse_peer(sequencer) {
+            sender.send(ChainMessage::UnstakeSequencer(chain, pid, staking))?;
+        }
     }
+
+    Ok(None)
+}
+
+/// Find the latest scanned block which is still canonical, none if no reorg
+async fn find_ancestor(client: &Provider<Http>, tracker: &Tracker) -> Result<Option<u64>> {
+    let mut reorged = false;
+    for (&number, &hash) in tracker.hashes.iter().rev() {
+        let canonical = client.get_block(number).await?.and_then(|b| b.hash);
+        if canonical == Some(hash) {
+            return Ok(if reorged { Some(number) } else { None });
+        }
+        reorged = true;
+    }
+
+    // deeper than the kept hashes, rescan from the oldest one
+    Ok(tracker
+        .hashes
+        .first_key_value()
+        .map(|(&number, _)| number.saturating_sub(1)))
 }
 
 #[inline]
-fn parse_room(cid: U256) -> Option<RoomId> {
-    if cid > U256::from(RoomId::MAX) {
+fn parse_room(chain: usize, cid: U256) -> Option<RoomId> {
+    if cid > U256::from(u64::MAX) {
         None
     } else {
-        Some(cid.as_u64())
+        chain_room_id(chain, cid.as_u64())
     }
 }
 
@@ -358,18 +894,86 @@ fn _parse_peers(cpids: Vec<Address>) -> Vec<PeerId> {
     res
 }
 
-#[inline]
-fn _parse_pk(cpk: H256) -> Option<PublicKey> {
-    PublicKey::deserialize_with_mode(cpk.as_bytes(), Compress::Yes, Validate::Yes).ok()
-}
-
-#[inline]
-fn _parse_pks(cpks: Vec<H256>) -> Vec<PublicKey> {
-    let mut res = vec![];
-    for cpk in cpks {
-        if let Some(p) = _parse_pk(cpk) {
-            res.push(p)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The claim log of room 1 at the block
+    fn claim_log(block: u64) -> Log {
+        Log {
+            topics: vec![ClaimRoom::signature()],
+            data: ethers::abi::encode(&[ethers::abi::Token::Uint(1.into())]).into(),
+            block_number: Some(block.into()),
+            transaction_hash: Some(H256::repeat_byte(block as u8)),
+            log_index: Some(0.into()),
+            ..Default::default()
         }
     }
-    res
+
+    fn claimed(receiver: &mut UnboundedReceiver<ChainMessage>) -> bool {
+        matches!(receiver.try_recv(), Ok(ChainMessage::ClaimRoom(1)))
+    }
+
+    #[test]
+    fn forward_after_quorum() {
+        let (sender, mut receiver) = chain_channel();
+        let mut forwarded = Forwarded::new(0, 2);
+
+        forwarded.forward(0, claim_log(10), &sender).unwrap();
+        forwarded.forward(0, claim_log(10), &sender).unwrap();
+        assert!(receiver.try_recv().is_err());
+
+        forwarded.forward(1, claim_log(10), &sender).unwrap();
+        assert!(claimed(&mut receiver));
+
+        // sent once
+        forwarded.forward(2, claim_log(10), &sender).unwrap();
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn disagreements_of_provider() {
+        let (sender, _receiver) = chain_channel();
+        let mut forwarded = Forwarded::new(0, 2);
+        forwarded.forward(0, claim_log(10), &sender).unwrap();
+        forwarded.forward(1, claim_log(10), &sender).unwrap();
+
+        assert!(forwarded.disagreements(0, 0, 20).is_empty());
+        assert!(forwarded.disagreements(2, 11, 20).is_empty());
+        let missing = forwarded.disagreements(2, 0, 20);
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].0, 10);
+    }
+
+    #[test]
+    fn late_reports_until_pruned() {
+        let (sender, mut receiver) = chain_channel();
+        let mut forwarded = Forwarded::new(0, 2);
+        forwarded.forward(0, claim_log(10), &sender).unwrap();
+        forwarded.forward(0, claim_log(20), &sender).unwrap();
+
+        // the lagging provider still counts before the log is pruned
+        forwarded.prune(10 + KEEP_HASHES as u64);
+        forwarded.forward(1, claim_log(10), &sender).unwrap();
+        assert!(claimed(&mut receiver));
+
+        forwarded.prune(21 + KEEP_HASHES as u64);
+        forwarded.forward(1, claim_log(20), &sender).unwrap();
+        assert!(receiver.try_recv().is_err());
+        assert!(forwarded.logs.is_empty());
+    }
+
+    #[test]
+    fn rollback_reverts_sent_logs() {
+        let (sender, mut receiver) = chain_channel();
+        let mut forwarded = Forwarded::new(0, 1);
+        forwarded.forward(0, claim_log(10), &sender).unwrap();
+        assert!(claimed(&mut receiver));
+
+        forwarded.rollback(9, &sender).unwrap();
+        assert!(forwarded.logs.is_empty());
+        // report again after reorg
+        forwarded.forward(0, claim_log(10), &sender).unwrap();
+        assert!(claimed(&mut receiver));
+    }
 }
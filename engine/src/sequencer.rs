@@ -0,0 +1,110 @@
+use ethers::prelude::U256;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use z4_types::{split_room_id, PeerId, RoomId};
+
+/// The sequencer staked in the room market
+#[derive(Clone, Debug, Default)]
+pub struct Sequencer {
+    /// http url of the sequencer
+    pub http: String,
+    /// websocket url of the sequencer
+    pub websocket: String,
+    /// staking amount after the latest stake/unstake
+    pub staking: U256,
+    /// rooms which accepted by the sequencer and not over
+    pub rooms: HashSet<RoomId>,
+}
+
+/// Registry of active sequencers, synced from the scanned chain events,
+/// keyed by the namespace of chain market and the sequencer account,
+/// the same account staked in different markets are different sequencers
+#[derive(Default)]
+pub struct Sequencers {
+    sequencers: HashMap<(usize, PeerId), Sequencer>,
+}
+
+impl Sequencers {
+    /// Sequencer staked or updated its endpoints in the chain market
+    pub fn stake(
+        &mut self,
+        chain: usize,
+        peer: PeerId,
+        http: String,
+        websocket: String,
+        staking: U256,
+    ) {
+        let sequencer = self.sequencers.entry((chain, peer)).or_default();
+        sequencer.http = http;
+        sequencer.websocket = websocket;
+        sequencer.staking = staking;
+    }
+
+    /// Sequencer unstaked in the chain market, removed when nothing staked and no rooms
+    pub fn unstake(&mut self, chain: usize, peer: PeerId, staking: U256) {
+        if let Some(sequencer) = self.sequencers.get_mut(&(chain, peer)) {
+            sequencer.staking = staking;
+            if staking.is_zero() && sequencer.rooms.is_empty() {
+                self.sequencers.remove(&(chain, peer));
+            }
+        }
+    }
+
+    /// Sequencer accepted the room, in the chain market of the room
+    pub fn accept(&mut self, room: RoomId, peer: PeerId, websocket: String) {
+        let chain = split_room_id(room).0;
+        let sequencer = self.sequencers.entry((chain, peer)).or_default();
+        sequencer.websocket = websocket;
+        sequencer.rooms.insert(room);
+    }
+
+    /// Room is over or claimed
+    pub fn over(&mut self, room: RoomId) {
+        let chain = split_room_id(room).0;
+        self.sequencers.retain(|(c, _), s| {
+            if *c == chain {
+                s.rooms.remove(&room);
+            }
+            !(s.staking.is_zero() && s.rooms.is_empty())
+        });
+    }
+
+    /// Get the sequencer in the chain market
+    pub fn get(&self, chain: usize, peer: &PeerId) -> Option<&Sequencer> {
+        self.sequencers.get(&(chain, *peer))
+    }
+
+    /// Iterate all sequencers with the namespace of chain market
+    pub fn iter(&self) -> impl Iterator<Item = (&(usize, PeerId), &Sequencer)> {
+        self.sequencers.iter()
+    }
+
+    /// The number of sequencers
+    pub fn len(&self) -> usize {
+        self.sequencers.len()
+    }
+
+    /// Check if no sequencer
+    pub fn is_empty(&self) -> bool {
+        self.sequencers.is_empty()
+    }
+
+    /// Convert to json value for rpc
+    pub fn to_value(&self) -> Value {
+        let list: Vec<Value> = self
+            .sequencers
+            .iter()
+            .map(|((chain, peer), s)| {
+                json!({
+                    "chain": chain,
+                    "sequencer": peer.to_hex(),
+                    "http": s.http,
+                    "websocket": s.websocket,
+                    "staking": s.staking.to_string(),
+                    "rooms": s.rooms.len(),
+                })
+            })
+            .collect();
+        Value::Array(list)
+    }
+}
@@ -0,0 +1,170 @@
+use serde_json::{json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Keep the finished room status for diagnostics (seconds)
+pub const STATUS_KEEP: u64 = 3600;
+
+/// The lifecycle status of room
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoomStatus {
+    /// created on chain, waiting players and start
+    Pending,
+    /// started on chain, sending the accept transaction
+    Accepting,
+    /// accepted by sequencer, game is running
+    Running,
+    /// game over, proving the result
+    Proving,
+    /// proof produced, submitting the settlement transaction
+    Submitting,
+    /// settled on chain
+    Settled,
+    /// settlement failed
+    Failed,
+    /// expired or closed without settlement
+    Expired,
+}
+
+impl RoomStatus {
+    /// The status name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoomStatus::Pending => "pending",
+            RoomStatus::Accepting => "accepting",
+            RoomStatus::Running => "running",
+            RoomStatus::Proving => "proving",
+            RoomStatus::Submitting => "submitting",
+            RoomStatus::Settled => "settled",
+            RoomStatus::Failed => "failed",
+            RoomStatus::Expired => "expired",
+        }
+    }
+
+    /// Check if the room is finished
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            RoomStatus::Settled | RoomStatus::Failed | RoomStatus::Expired
+        )
+    }
+
+    /// Check if can transit to the next status
+    pub fn can_transit(&self, next: RoomStatus) -> bool {
+        use RoomStatus::*;
+
+        match (self, next) {
+            (Pending, Accepting) => true,
+            (Pending | Accepting, Running) => true,
+            (Running, Proving) => true,
+            (Proving, Submitting) => true,
+            (Submitting, Failed) => true,
+            // the chain is the source of truth, room maybe over by other sequencer
+            (Failed, Settled) => true,
+            (s, Settled) => !s.is_final(),
+            (s, Expired) => !s.is_final(),
+            _ => false,
+        }
+    }
+}
+
+/// The room status with transitions history
+pub struct RoomLifecycle {
+    /// current status
+    pub status: RoomStatus,
+    /// all transitions with unix timestamp (seconds)
+    pub history: Vec<(RoomStatus, u64)>,
+}
+
+impl RoomLifecycle {
+    /// New room lifecycle with pending status
+    pub fn new() -> Self {
+        Self {
+            status: RoomStatus::Pending,
+            history: vec![(RoomStatus::Pending, now())],
+        }
+    }
+
+    /// Transit to next status, return false if transition is invalid
+    pub fn transit(&mut self, next: RoomStatus) -> bool {
+        if self.status.can_transit(next) {
+            self.status = next;
+            self.history.push((next, now()));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The time of the latest transition
+    pub fn updated(&self) -> u64 {
+        self.history.last().map(|(_, t)| *t).unwrap_or(0)
+    }
+
+    /// The status info as json
+    pub fn to_value(&self) -> Value {
+        let history: Vec<Value> = self
+            .history
+            .iter()
+            .map(|(s, t)| json!({ "status": s.as_str(), "at": t }))
+            .collect();
+        json!({
+            "status": self.status.as_str(),
+            "history": history,
+        })
+    }
+}
+
+impl Default for RoomLifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Current unix timestamp (seconds)
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lifecycle_until_settled() {
+        let mut lifecycle = RoomLifecycle::new();
+        assert!(lifecycle.transit(RoomStatus::Accepting));
+        assert!(lifecycle.transit(RoomStatus::Running));
+        assert!(lifecycle.transit(RoomStatus::Proving));
+        assert!(lifecycle.transit(RoomStatus::Submitting));
+        assert!(lifecycle.transit(RoomStatus::Settled));
+        assert_eq!(lifecycle.status, RoomStatus::Settled);
+        assert_eq!(lifecycle.history.len(), 6);
+    }
+
+    #[test]
+    fn invalid_transitions_ignored() {
+        let mut lifecycle = RoomLifecycle::new();
+        assert!(!lifecycle.transit(RoomStatus::Proving));
+        assert!(!lifecycle.transit(RoomStatus::Pending));
+        assert_eq!(lifecycle.status, RoomStatus::Pending);
+        assert_eq!(lifecycle.history.len(), 1);
+
+        // local rooms are running without accepting
+        assert!(lifecycle.transit(RoomStatus::Running));
+        assert!(!lifecycle.transit(RoomStatus::Accepting));
+    }
+
+    #[test]
+    fn final_status() {
+        assert!(RoomStatus::Running.can_transit(RoomStatus::Expired));
+        assert!(!RoomStatus::Expired.can_transit(RoomStatus::Settled));
+        assert!(!RoomStatus::Settled.can_transit(RoomStatus::Expired));
+        assert!(!RoomStatus::Settled.can_transit(RoomStatus::Failed));
+        // the room maybe settled by other sequencer after failed
+        assert!(RoomStatus::Failed.can_transit(RoomStatus::Settled));
+        assert!(RoomStatus::Submitting.can_transit(RoomStatus::Failed));
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use z4_types::{Error, Result};
+
+/// Key-value storage for engine states which need persist
+pub trait Storage: Send + Sync {
+    /// Get the value of key
+    fn get(&self, key: &str) -> Option<Vec<u8>>;
+
+    /// Put the value of key
+    fn put(&self, key: &str, value: Vec<u8>) -> Result<()>;
+
+    /// Remove the key
+    fn remove(&self, key: &str) -> Result<()>;
+}
+
+/// Storage in memory, states will be lost when restart
+#[derive(Default)]
+pub struct MemoryStorage {
+    values: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.values.lock().ok()?.get(key).cloned()
+    }
+
+    fn put(&self, key: &str, value: Vec<u8>) -> Result<()> {
+        self.values
+            .lock()
+            .map_err(|_| Error::Anyhow("storage poisoned".to_owned()))?
+            .insert(key.to_owned(), value);
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        self.values
+            .lock()
+            .map_err(|_| Error::Anyhow("storage poisoned".to_owned()))?
+            .remove(key);
+        Ok(())
+    }
+}
+
+/// Storage in files, every key is a file in the directory
+pub struct FileStorage {
+    path: PathBuf,
+}
+
+impl FileStorage {
+    /// Create the storage in the directory
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        std::fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+
+    #[inline]
+    fn file(&self, key: &str) -> PathBuf {
+        let name: String = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        self.path.join(name)
+    }
+}
+
+impl Storage for FileStorage {
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        std::fs::read(self.file(key)).ok()
+    }
+
+    fn put(&self, key: &str, value: Vec<u8>) -> Result<()> {
+        // write to temp file and rename, avoid broken file when crash
+        let file = self.file(key);
+        let tmp = file.with_extension("tmp");
+        std::fs::write(&tmp, value)?;
+        std::fs::rename(tmp, file)?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        let file = self.file(key);
+        if file.exists() {
+            std::fs::remove_file(file)?;
+        }
+        Ok(())
+    }
+}
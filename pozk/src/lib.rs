@@ -19,8 +19,8 @@ use tokio_tungstenite::{
     MaybeTlsStream, WebSocketStream,
 };
 use z4_types::{
-    address_to_peer, handle_tasks, peer_to_address, Error, HandleResult, Handler, Param, Player,
-    Result, TaskMessage, PLAYER_BYTES_LEN,
+    address_to_peer, handle_tasks, peer_to_address, Error, HandleResult, Handler, Param, PeerId,
+    Player, Result, RoomContext, TaskMessage, PLAYER_BYTES_LEN,
 };
 
 /// Store the room info
@@ -70,6 +70,15 @@ impl<H: Handler> Engine<H> {
         // 3. build the handler
         let mut players = HashMap::new();
         players.insert(peer_to_address(player.peer), false);
+        // the pozk room has no game id, seed and sequencer
+        let mut ctx = RoomContext::new(
+            rid,
+            Address::zero(),
+            vec![player],
+            H::viewable(),
+            [0u8; 32],
+            PeerId::default(),
+        );
         let (raw_handler, tasks) = H::pozk_create(player, publics_bytes.to_vec(), rid)
             .await
             .unwrap();
@@ -107,7 +116,7 @@ impl<H: Handler> Engine<H> {
                                 if engine.players.contains_key(&peer) {
                                     // Online
                                     let mut handler = engine.handler.lock().await;
-                                    let res = handler.online(address_to_peer(peer)).await;
+                                    let res = handler.online(&ctx, address_to_peer(peer)).await;
                                     drop(handler);
 
                                     if let Ok(true) =
@@ -122,6 +131,7 @@ impl<H: Handler> Engine<H> {
                                     if let Ok(player) = Player::from_bytes(&data) {
                                         // Join
                                         let params = data.split_off(PLAYER_BYTES_LEN);
+                                        ctx.players.push(player);
                                         let mut handler = engine.handler.lock().await;
                                         let res = handler.pozk_join(player, params).await;
                                         drop(handler);
@@ -141,7 +151,7 @@ impl<H: Handler> Engine<H> {
                             }
                             TextMessage::ConnectViewer(peer) => {
                                 let mut handler = engine.handler.lock().await;
-                                let res = handler.viewer_online(address_to_peer(peer)).await;
+                                let res = handler.viewer_online(&ctx, address_to_peer(peer)).await;
                                 drop(handler);
 
                                 if let Ok(true) = handle_res::<H>(res, false, &mut ws_stream).await
@@ -154,7 +164,7 @@ impl<H: Handler> Engine<H> {
                                 if engine.players.contains_key(&peer) {
                                     // Offline
                                     let mut handler = engine.handler.lock().await;
-                                    let res = handler.offline(address_to_peer(peer)).await;
+                                    let res = handler.offline(&ctx, address_to_peer(peer)).await;
                                     drop(handler);
 
                                     if let Ok(true) =
@@ -167,7 +177,7 @@ impl<H: Handler> Engine<H> {
                             }
                             TextMessage::CloseViewer(peer) => {
                                 let mut handler = engine.handler.lock().await;
-                                let res = handler.viewer_offline(address_to_peer(peer)).await;
+                                let res = handler.viewer_offline(&ctx, address_to_peer(peer)).await;
                                 drop(handler);
 
                                 if let Ok(true) = handle_res::<H>(res, false, &mut ws_stream).await
@@ -183,7 +193,7 @@ impl<H: Handler> Engine<H> {
                                 };
 
                                 let mut handler = engine.handler.lock().await;
-                                let res = handler.handle(address_to_peer(peer), param).await;
+                                let res = handler.handle(&ctx, address_to_peer(peer), param).await;
                                 drop(handler);
 
                                 if let Ok(true) = handle_res::<H>(res, false, &mut ws_stream).await
@@ -207,7 +217,7 @@ impl<H: Handler> Engine<H> {
                             };
 
                             let mut handler = engine.handler.lock().await;
-                            let res = handler.handle(address_to_peer(peer), param).await;
+                            let res = handler.handle(&ctx, address_to_peer(peer), param).await;
                             drop(handler);
 
                             if let Ok(true) = handle_res::<H>(res, true, &mut ws_stream).await {
@@ -267,6 +277,7 @@ async fn handle_res<H: Handler>(
         one,
         over,
         started,
+        ..
     } = hres;
 
     if started {
@@ -0,0 +1,27 @@
+use crate::{Param, Player, RoomContext};
+
+/// Server-side bot which play in a seat of room
+#[async_trait::async_trait]
+pub trait Bot: Send {
+    /// Request/Response params, same as the game handler
+    type Param: Param;
+
+    /// Next time for bot to act (milliseconds)
+    fn timer(&self) -> u64 {
+        1000
+    }
+
+    /// Receive the message which deliver to this seat,
+    /// return the params which will be handled as from this seat
+    async fn receive(&mut self, _ctx: &RoomContext, _param: Self::Param) -> Vec<Self::Param> {
+        vec![]
+    }
+
+    /// Act on the schedule, return the params which will be handled as from this seat
+    async fn act(&mut self, _ctx: &RoomContext) -> Vec<Self::Param> {
+        vec![]
+    }
+}
+
+/// Type helper for bots with their seats
+pub type Bots<P> = Vec<(Player, Box<dyn Bot<Param = P>>)>;
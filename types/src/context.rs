@@ -0,0 +1,54 @@
+use std::time::Instant;
+
+use crate::{GameId, PeerId, Player, RoomId};
+
+/// The context of running room, provided by the engine
+#[derive(Clone, Debug)]
+pub struct RoomContext {
+    /// The room id
+    pub room: RoomId,
+    /// The game id/address
+    pub game: GameId,
+    /// The players of room
+    pub players: Vec<Player>,
+    /// The room is viewable for others
+    pub viewable: bool,
+    /// The random seed of room
+    pub seed: [u8; 32],
+    /// The sequencer which running the room
+    pub sequencer: PeerId,
+    /// The time when room started
+    started: Instant,
+}
+
+impl RoomContext {
+    /// New room context, and start the room clock
+    pub fn new(
+        room: RoomId,
+        game: GameId,
+        players: Vec<Player>,
+        viewable: bool,
+        seed: [u8; 32],
+        sequencer: PeerId,
+    ) -> Self {
+        Self {
+            room,
+            game,
+            players,
+            viewable,
+            seed,
+            sequencer,
+            started: Instant::now(),
+        }
+    }
+
+    /// Monotonic room clock, milliseconds since room started
+    pub fn clock(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    /// Get the player by peer id
+    pub fn player(&self, peer: &PeerId) -> Option<&Player> {
+        self.players.iter().find(|p| &p.peer == peer)
+    }
+}
@@ -15,6 +15,14 @@ pub enum Error {
     Serialize,
     /// invalid secret key
     SecretKey,
+    /// Too many messages in a short time
+    RateLimit,
+    /// The message payload is too large
+    PayloadTooLarge,
+    /// The room is started and locked, cannot join
+    RoomLocked,
+    /// Not authorized to call the method
+    Auth,
     /// Anyhow error
     Anyhow(String),
     /// ZK error,
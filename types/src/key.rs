@@ -1,5 +1,6 @@
 use ark_ec::PrimeGroup;
 use ark_ed_on_bn254::{EdwardsAffine, EdwardsProjective, Fr};
+use ark_serialize::{CanonicalDeserialize, Compress, Validate};
 use ark_std::{
     rand::{CryptoRng, RngCore},
     UniformRand,
@@ -18,6 +19,11 @@ pub fn generate_keypair<R: CryptoRng + RngCore>(prng: &mut R) -> (SecretKey, Pub
     (sk, EdwardsAffine::from(pk))
 }
 
+/// Decode the compressed public key, none if it is not a valid curve point
+pub fn parse_public_key(bytes: &[u8]) -> Option<PublicKey> {
+    PublicKey::deserialize_with_mode(bytes, Compress::Yes, Validate::Yes).ok()
+}
+
 /// Sign for zk-friendly
 pub fn sign() {
     //
@@ -27,3 +33,25 @@ pub fn sign() {
 pub fn verify() {
     //
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_serialize::CanonicalSerialize;
+
+    #[test]
+    fn parse_valid_public_key() {
+        let pk = EdwardsAffine::from(EdwardsProjective::generator() * Fr::from(7u64));
+        let mut bytes = vec![];
+        pk.serialize_with_mode(&mut bytes, Compress::Yes).unwrap();
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(parse_public_key(&bytes), Some(pk));
+    }
+
+    #[test]
+    fn reject_invalid_public_key() {
+        assert_eq!(parse_public_key(&[0u8; 32]), None);
+        assert_eq!(parse_public_key(&[255u8; 32]), None);
+        assert_eq!(parse_public_key(&[1u8; 16]), None);
+    }
+}
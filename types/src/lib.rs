@@ -1,16 +1,22 @@
+mod bot;
+mod context;
 mod error;
 mod key;
 mod network;
+mod system;
 mod task;
 mod utils;
 
 use serde::{Deserialize, Serialize};
 pub use serde_json::{json, Value};
 
+pub use bot::{Bot, Bots};
+pub use context::RoomContext;
 pub use error::Error;
 pub use ethereum_types::{Address, H160};
 pub use key::*;
 pub use network::*;
+pub use system::*;
 pub use task::*;
 pub use tdn_types::primitives::PeerId;
 pub use utils::*;
@@ -29,6 +35,8 @@ pub struct HandleResult<P: Param> {
     pub over: bool,
     /// When need waiting others, can use started = false (for PoZK)
     pub started: bool,
+    /// Replace the seat of player to new player, old peer => new player
+    pub replace: Vec<(PeerId, Player)>,
 }
 
 impl<P: Param> HandleResult<P> {
@@ -51,6 +59,11 @@ impl<P: Param> HandleResult<P> {
     pub fn started(&mut self) {
         self.started = true;
     }
+
+    /// Replace the seat of player to new player (new peer, same account or a bot)
+    pub fn replace(&mut self, old: PeerId, player: Player) {
+        self.replace.push((old, player));
+    }
 }
 
 /// Serialize & deserialize for params
@@ -88,6 +101,15 @@ pub trait Task: Send + Sync {
         &mut self,
         state: &mut Self::H,
     ) -> Result<HandleResult<<Self::H as Handler>::Param>>;
+
+    /// Execute the task with room context, default is run
+    async fn run_with_context(
+        &mut self,
+        state: &mut Self::H,
+        _ctx: &RoomContext,
+    ) -> Result<HandleResult<<Self::H as Handler>::Param>> {
+        self.run(state).await
+    }
 }
 
 /// Type helper for tasks
@@ -99,12 +121,24 @@ pub struct Player {
     pub account: Address,
     pub peer: PeerId,
     pub signer: [u8; 32],
+    /// The public key parsed from signer, none if signer is not a valid key
+    pub pk: Option<PublicKey>,
 }
 
 /// Player bytes length
 pub const PLAYER_BYTES_LEN: usize = 72;
 
 impl Player {
+    /// Create player with the signer public key
+    pub fn new(account: Address, peer: PeerId, signer: [u8; 32]) -> Player {
+        Player {
+            account,
+            peer,
+            signer,
+            pk: parse_public_key(&signer),
+        }
+    }
+
     /// deserialize Player from bytes
     pub fn from_bytes(bytes: &[u8]) -> Result<Player> {
         if bytes.len() < PLAYER_BYTES_LEN {
@@ -118,11 +152,11 @@ impl Player {
         peer_bytes.copy_from_slice(&bytes[20..40]);
         signer_bytes.copy_from_slice(&bytes[40..72]);
 
-        Ok(Player {
-            account: H160(account_bytes),
-            peer: PeerId(peer_bytes),
-            signer: signer_bytes,
-        })
+        Ok(Player::new(
+            H160(account_bytes),
+            PeerId(peer_bytes),
+            signer_bytes,
+        ))
     }
 
     /// serialize Player to bytes
@@ -145,6 +179,13 @@ pub trait Handler: Send + Sized + 'static {
         false
     }
 
+    /// Require valid public keys of players, the room will not be accepted
+    /// and players cannot join when some player's key is invalid,
+    /// default is required, games without player keys can opt out
+    fn validate_keys() -> bool {
+        true
+    }
+
     /// Accept params when submit to chain
     async fn chain_accept(_players: &[Player]) -> Vec<u8> {
         vec![]
@@ -169,7 +210,7 @@ pub trait Handler: Send + Sized + 'static {
         None
     }
 
-    /// New player join from PoZK
+    /// New player join from PoZK, the room is not hosted by engine, so no room context
     async fn pozk_join(
         &mut self,
         _player: Player,
@@ -179,36 +220,131 @@ pub trait Handler: Send + Sized + 'static {
     }
 
     /// New Viewer online if viewable is true
-    async fn viewer_online(&mut self, _peer: PeerId) -> Result<HandleResult<Self::Param>> {
+    async fn viewer_online(
+        &mut self,
+        _ctx: &RoomContext,
+        _peer: PeerId,
+    ) -> Result<HandleResult<Self::Param>> {
         Ok(HandleResult::default())
     }
 
     /// New Viewer offline if viewable is true
-    async fn viewer_offline(&mut self, _peer: PeerId) -> Result<HandleResult<Self::Param>> {
+    async fn viewer_offline(
+        &mut self,
+        _ctx: &RoomContext,
+        _peer: PeerId,
+    ) -> Result<HandleResult<Self::Param>> {
         Ok(HandleResult::default())
     }
 
     /// When player online
-    async fn online(&mut self, _peer: PeerId) -> Result<HandleResult<Self::Param>> {
+    async fn online(
+        &mut self,
+        _ctx: &RoomContext,
+        _peer: PeerId,
+    ) -> Result<HandleResult<Self::Param>> {
         Ok(HandleResult::default())
     }
 
     /// When player offline
-    async fn offline(&mut self, _peer: PeerId) -> Result<HandleResult<Self::Param>> {
+    async fn offline(
+        &mut self,
+        _ctx: &RoomContext,
+        _peer: PeerId,
+    ) -> Result<HandleResult<Self::Param>> {
         Ok(HandleResult::default())
     }
 
     /// Handle message in a room
     async fn handle(
         &mut self,
+        _ctx: &RoomContext,
         _peer: PeerId,
         _param: Self::Param,
     ) -> Result<HandleResult<Self::Param>> {
         Ok(HandleResult::default())
     }
 
+    /// Open the lobby after room created, players can join/leave until it is started,
+    /// default is locked, the players of room are fixed when created
+    fn lobby(&self) -> bool {
+        false
+    }
+
+    /// New player join when room is not started (lobby), default is rejected.
+    /// The room will be locked when HandleResult started is set
+    async fn join(
+        &mut self,
+        _ctx: &RoomContext,
+        _player: Player,
+        _params: Vec<u8>,
+    ) -> Result<HandleResult<Self::Param>> {
+        Err(Error::RoomLocked)
+    }
+
+    /// Take the bots which attach to seats, called after room created and seats replaced,
+    /// bots can be configured when chain_create
+    fn take_bots(&mut self) -> Bots<Self::Param> {
+        vec![]
+    }
+
+    /// Fill the empty seats with bots when the room is waiting players too long
+    async fn fill_seats(&mut self, _ctx: &RoomContext) -> Bots<Self::Param> {
+        vec![]
+    }
+
+    /// Player leave the room, the seat will be removed when room not started
+    async fn leave(
+        &mut self,
+        _ctx: &RoomContext,
+        _peer: PeerId,
+    ) -> Result<HandleResult<Self::Param>> {
+        Ok(HandleResult::default())
+    }
+
+    /// Player forfeit the game, handler can over the game or replace the seat
+    async fn forfeit(
+        &mut self,
+        _ctx: &RoomContext,
+        _peer: PeerId,
+    ) -> Result<HandleResult<Self::Param>> {
+        Ok(HandleResult::default())
+    }
+
+    /// When room is idle too long, return result with over to prove it,
+    /// otherwise the room will be closed without settlement
+    async fn on_idle(&mut self, _ctx: &RoomContext) -> Result<HandleResult<Self::Param>> {
+        Ok(HandleResult::default())
+    }
+
     /// Generate proof for this game result, when find game is over
     async fn prove(&mut self) -> Result<(Vec<u8>, Vec<u8>)>;
+
+    /// Projection of the broadcast message for viewers, none will not send to viewers,
+    /// default is same as players
+    fn view(&self, param: &Self::Param) -> Option<Self::Param> {
+        Self::Param::from_bytes(param.to_bytes()).ok()
+    }
+
+    /// Decode the param from p2p message bytes, default is Param::from_bytes
+    fn decode(&self, bytes: Vec<u8>) -> Result<Self::Param> {
+        Self::Param::from_bytes(bytes)
+    }
+
+    /// Encode the param to p2p message bytes, default is Param::to_bytes
+    fn encode(&self, param: &Self::Param) -> Vec<u8> {
+        param.to_bytes()
+    }
+
+    /// Current visible state for the late viewers when online
+    fn snapshot(&self) -> Option<Self::Param> {
+        None
+    }
+
+    /// Current state of the room for operators debugging
+    fn debug_state(&self) -> Value {
+        Value::Null
+    }
 }
 
 impl Param for Value {
@@ -37,6 +37,16 @@ impl Network {
         }
     }
 
+    /// Default blocks of confirmation before the logs are handled
+    pub fn confirmations(&self) -> u64 {
+        match self {
+            Network::Localhost => 0,
+            Network::Holesky | Network::Sepolia => 3,
+            Network::OpBNBTestnet => 15,
+            Network::Other => 6,
+        }
+    }
+
     pub fn from_chain_id(chain_id: u64) -> Self {
         match chain_id {
             17000 => Network::Holesky,
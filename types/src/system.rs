@@ -0,0 +1,102 @@
+use crate::{MethodValues, PeerId, Value};
+
+/// Reserved method of keep the connection alive
+pub const Z4_PING: &str = "z4_ping";
+
+/// Reserved method of response the ping
+pub const Z4_PONG: &str = "z4_pong";
+
+/// Reserved method of player join the room when not started
+pub const Z4_JOIN: &str = "z4_join";
+
+/// Reserved method of player leave the room
+pub const Z4_LEAVE: &str = "z4_leave";
+
+/// Reserved method of player forfeit the game
+pub const Z4_FORFEIT: &str = "z4_forfeit";
+
+/// Reserved method of send chat message to the room
+pub const Z4_CHAT_SEND: &str = "z4_chat_send";
+
+/// Reserved method of room chat message
+pub const Z4_CHAT: &str = "z4_chat";
+
+/// Reserved method of room chat history
+pub const Z4_CHAT_HISTORY: &str = "z4_chat_history";
+
+/// Reserved method of room presence
+pub const Z4_PRESENCE: &str = "z4_presence";
+
+/// The engine messages in reserved namespace, not handled by game handler
+#[derive(Clone, Debug)]
+pub enum SystemMessage {
+    /// Chat message in the room, sender, text, unix timestamp
+    Chat(PeerId, String, u64),
+    /// Chat history of the room
+    ChatHistory(Vec<(PeerId, String, u64)>),
+    /// Player/viewer online or offline, peer, is online, viewers count
+    Presence(PeerId, bool, usize),
+}
+
+impl SystemMessage {
+    /// Check the method is in reserved namespace, game methods cannot start with `z4_`
+    pub fn is_system(method: &str) -> bool {
+        method.starts_with("z4_")
+    }
+
+    /// Convert to method values
+    pub fn to_values(&self) -> MethodValues {
+        match self {
+            SystemMessage::Chat(peer, text, at) => MethodValues::new(
+                Z4_CHAT,
+                vec![peer.to_hex().into(), text.clone().into(), (*at).into()],
+            ),
+            SystemMessage::ChatHistory(list) => {
+                let list: Vec<Value> = list
+                    .iter()
+                    .map(|(peer, text, at)| {
+                        Value::Array(vec![
+                            peer.to_hex().into(),
+                            text.clone().into(),
+                            (*at).into(),
+                        ])
+                    })
+                    .collect();
+                MethodValues::new(Z4_CHAT_HISTORY, list)
+            }
+            SystemMessage::Presence(peer, online, viewers) => MethodValues::new(
+                Z4_PRESENCE,
+                vec![peer.to_hex().into(), (*online).into(), (*viewers).into()],
+            ),
+        }
+    }
+
+    /// Decode from method values, none if not a system message
+    pub fn from_values(values: &MethodValues) -> Option<Self> {
+        match values.method.as_str() {
+            Z4_CHAT => chat_item(&values.params).map(|(p, t, a)| SystemMessage::Chat(p, t, a)),
+            Z4_CHAT_HISTORY => {
+                let list = values
+                    .params
+                    .iter()
+                    .filter_map(|v| v.as_array().and_then(|v| chat_item(v)))
+                    .collect();
+                Some(SystemMessage::ChatHistory(list))
+            }
+            Z4_PRESENCE => {
+                let peer = PeerId::from_hex(values.params.first()?.as_str()?).ok()?;
+                let online = values.params.get(1)?.as_bool()?;
+                let viewers = values.params.get(2)?.as_u64()? as usize;
+                Some(SystemMessage::Presence(peer, online, viewers))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn chat_item(params: &[Value]) -> Option<(PeerId, String, u64)> {
+    let peer = PeerId::from_hex(params.first()?.as_str()?).ok()?;
+    let text = params.get(1)?.as_str()?.to_owned();
+    let at = params.get(2)?.as_u64()?;
+    Some((peer, text, at))
+}
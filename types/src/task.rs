@@ -1,26 +1,52 @@
 use std::sync::Arc;
 use tokio::{
-    sync::{mpsc::UnboundedSender, Mutex},
+    sync::{mpsc::UnboundedSender, Mutex, RwLock},
+    task::AbortHandle,
     time::sleep,
 };
 
-use crate::{HandleResult, Handler, RoomId, Task, Tasks};
+use crate::{HandleResult, Handler, RoomContext, RoomId, Task, Tasks};
 
 /// Task message type
 pub enum TaskMessage<H: Handler> {
     Result(RoomId, HandleResult<H::Param>),
 }
 
-/// Handle and listening tasks
+/// Handle and listening tasks, return the handles to abort them
 pub fn handle_tasks<H: Handler>(
     room_id: RoomId,
     tasks: Tasks<H>,
     handler: Arc<Mutex<H>>,
     sender: UnboundedSender<TaskMessage<H>>,
-) {
-    for task in tasks {
-        tokio::spawn(running(room_id, task, handler.clone(), sender.clone()));
-    }
+) -> Vec<AbortHandle> {
+    spawn_tasks(room_id, tasks, handler, sender, None)
+}
+
+/// Handle and listening tasks with room context, return the handles to abort them
+pub fn handle_tasks_with_context<H: Handler>(
+    room_id: RoomId,
+    ctx: Arc<RwLock<RoomContext>>,
+    tasks: Tasks<H>,
+    handler: Arc<Mutex<H>>,
+    sender: UnboundedSender<TaskMessage<H>>,
+) -> Vec<AbortHandle> {
+    spawn_tasks(room_id, tasks, handler, sender, Some(ctx))
+}
+
+fn spawn_tasks<H: Handler>(
+    room_id: RoomId,
+    tasks: Tasks<H>,
+    handler: Arc<Mutex<H>>,
+    sender: UnboundedSender<TaskMessage<H>>,
+    ctx: Option<Arc<RwLock<RoomContext>>>,
+) -> Vec<AbortHandle> {
+    tasks
+        .into_iter()
+        .map(|task| {
+            let running = running(room_id, task, handler.clone(), sender.clone(), ctx.clone());
+            tokio::spawn(running).abort_handle()
+        })
+        .collect()
 }
 
 /// Loop listening task
@@ -29,12 +55,22 @@ async fn running<H: Handler>(
     mut task: Box<dyn Task<H = H>>,
     handler: Arc<Mutex<H>>,
     sender: UnboundedSender<TaskMessage<H>>,
+    ctx: Option<Arc<RwLock<RoomContext>>>,
 ) {
     loop {
         sleep(std::time::Duration::from_secs(task.timer())).await;
 
+        // snapshot the context, players maybe changed when running
+        let snapshot = match &ctx {
+            Some(ctx) => Some(ctx.read().await.clone()),
+            None => None,
+        };
         let mut handler_lock = handler.lock().await;
-        if let Ok(res) = task.run(&mut handler_lock).await {
+        let res = match &snapshot {
+            Some(ctx) => task.run_with_context(&mut handler_lock, ctx).await,
+            None => task.run(&mut handler_lock).await,
+        };
+        if let Ok(res) = res {
             let over = res.over;
             let _ = sender.send(TaskMessage::Result(room_id, res));
             if over {
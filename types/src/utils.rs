@@ -14,6 +14,50 @@ pub type GameId = Address;
 /// Z4 init room id/tdn group id
 pub const Z4_ROOM_MARKET_GROUP: RoomId = 4;
 
+/// Bits of the room id on chain, the higher bits are the namespace of chain market
+pub const ROOM_CHAIN_BITS: u32 = 32;
+
+/// Namespace of the rooms created locally without chain, not used by chain markets
+pub const LOCAL_ROOM_NAMESPACE: u64 = (1 << (64 - ROOM_CHAIN_BITS)) - 1;
+
+/// Namespace the room id on chain with the namespace of chain market.
+/// The main market (ROOM_MARKET) is namespace 0, same as the room id on chain,
+/// the extra markets (MARKETS) use the chain id as namespace,
+/// so clients can get the room id by `chain_id << ROOM_CHAIN_BITS | room`
+#[inline]
+pub fn chain_room_id(chain: usize, room: u64) -> Option<RoomId> {
+    if room >> ROOM_CHAIN_BITS != 0 || chain as u64 >= LOCAL_ROOM_NAMESPACE {
+        None
+    } else {
+        Some(((chain as u64) << ROOM_CHAIN_BITS) | room)
+    }
+}
+
+/// Namespace the room id which created locally without chain
+#[inline]
+pub fn local_room_id(room: u64) -> Option<RoomId> {
+    if room >> ROOM_CHAIN_BITS != 0 {
+        None
+    } else {
+        Some((LOCAL_ROOM_NAMESPACE << ROOM_CHAIN_BITS) | room)
+    }
+}
+
+/// Check the room is created locally without chain
+#[inline]
+pub fn is_local_room(id: RoomId) -> bool {
+    id >> ROOM_CHAIN_BITS == LOCAL_ROOM_NAMESPACE
+}
+
+/// Split the namespaced room id to the namespace of chain market and the room id on chain
+#[inline]
+pub fn split_room_id(id: RoomId) -> (usize, u64) {
+    (
+        (id >> ROOM_CHAIN_BITS) as usize,
+        id & ((1 << ROOM_CHAIN_BITS) - 1),
+    )
+}
+
 /// convert address to peer
 #[inline]
 pub fn address_to_peer(addr: Address) -> PeerId {
@@ -102,3 +146,34 @@ pub fn merge_json(a: &mut Value, b: &Value) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_room_id_namespaced() {
+        assert_eq!(chain_room_id(0, 7), Some(7));
+        assert_eq!(chain_room_id(3, 7), Some((3 << ROOM_CHAIN_BITS) | 7));
+        assert_eq!(split_room_id(chain_room_id(3, 7).unwrap()), (3, 7));
+        assert!(!is_local_room(chain_room_id(3, 7).unwrap()));
+    }
+
+    #[test]
+    fn chain_room_id_out_of_bounds() {
+        let max = (1u64 << ROOM_CHAIN_BITS) - 1;
+        assert_eq!(split_room_id(chain_room_id(1, max).unwrap()), (1, max));
+        assert_eq!(chain_room_id(0, max + 1), None);
+        assert_eq!(chain_room_id(LOCAL_ROOM_NAMESPACE as usize, 1), None);
+        assert!(chain_room_id(LOCAL_ROOM_NAMESPACE as usize - 1, 1).is_some());
+    }
+
+    #[test]
+    fn local_room_id_namespaced() {
+        let id = local_room_id(9).unwrap();
+        assert!(is_local_room(id));
+        assert_eq!(split_room_id(id), (LOCAL_ROOM_NAMESPACE as usize, 9));
+        assert_eq!(local_room_id(1 << ROOM_CHAIN_BITS), None);
+        assert!(!is_local_room(9));
+    }
+}